@@ -43,7 +43,8 @@ mv generated/languages.svg generated/languages_python.svg
 # Benchmark Rust version
 echo "=== Rust Version ==="
 START=$(date +%s.%N)
-./target/release/github-stats
+./target/release/github-stats collect
+./target/release/github-stats render
 END=$(date +%s.%N)
 RUST_TIME=$(echo "$END - $START" | bc)
 echo "Rust execution time: ${RUST_TIME} seconds"
@@ -1,76 +1,594 @@
-use anyhow::Result;
+use anyhow::{anyhow, Context, Result};
 use chrono::{DateTime, Duration, Utc};
+use reqwest::header::{
+    HeaderMap, HeaderValue, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED,
+};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use sha2::{Digest, Sha256};
-use std::{fs, path::PathBuf};
+use std::{
+    fmt,
+    fs::{self, File},
+    io::Write,
+    path::Path,
+    process,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use crate::{
+    cache_store::{CacheStore, Contents, FileStore, MemoryStore, SqliteStore, Stored},
+    config::{CacheBackend, CacheConfig, CachePolicy},
+    snapshot::format_version,
+};
+
+/// Version of the cache entry format written by this build. Entries of any
+/// other version are refetched.
+pub const CACHE_VERSION: u32 = 1;
 
 #[derive(Serialize, Deserialize)]
 struct CacheEntry {
+    #[serde(default = "first_version")]
+    version: u32,
+    /// The key the entry was stored under; file names are only its hash.
+    /// Empty in entries written before keys were recorded.
+    #[serde(default)]
+    key: String,
     timestamp: DateTime<Utc>,
+    /// When the entry stops being fresh. `None` in entries written before
+    /// expiries were recorded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    expires: Option<DateTime<Utc>>,
+    /// Until when the expired entry may stand in for a refresh that fails
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    stale_until: Option<DateTime<Utc>>,
     data: Value,
+    #[serde(flatten)]
+    validators: Validators,
+}
+
+/// Entries written before the format was versioned have the version 1 layout.
+fn first_version() -> u32 {
+    1
+}
+
+/// Why a cache file cannot be used.
+enum Unusable {
+    Corrupt(serde_json::Error),
+    Incompatible(u32),
+}
+
+impl fmt::Display for Unusable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Corrupt(e) => write!(f, "corrupt entry ({e})"),
+            Self::Incompatible(version) => write!(
+                f,
+                "entry has format version {version}, but this build uses version {CACHE_VERSION}"
+            ),
+        }
+    }
+}
+
+fn parse_entry(contents: &str) -> Result<CacheEntry, Unusable> {
+    let version = format_version(contents).map_err(Unusable::Corrupt)?;
+    if version != CACHE_VERSION {
+        return Err(Unusable::Incompatible(version));
+    }
+    serde_json::from_str(contents).map_err(Unusable::Corrupt)
+}
+
+/// What the server needs to tell whether a cached response is still current.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Validators {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub etag: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_modified: Option<String>,
+}
+
+impl Validators {
+    /// The `ETag` and `Last-Modified` of a response.
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        let header = |name| {
+            headers
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_string)
+        };
+        Self {
+            etag: header(ETAG),
+            last_modified: header(LAST_MODIFIED),
+        }
+    }
+
+    /// Make a request conditional, so that the server answers `304 Not
+    /// Modified` instead of the body if it has not changed.
+    pub fn apply(&self, headers: &mut HeaderMap) {
+        let values = [
+            (IF_NONE_MATCH, &self.etag),
+            (IF_MODIFIED_SINCE, &self.last_modified),
+        ];
+        for (name, value) in values {
+            if let Some(value) = value.as_deref().and_then(|v| HeaderValue::from_str(v).ok()) {
+                headers.insert(name, value);
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.etag.is_none() && self.last_modified.is_none()
+    }
+
+    /// These validators, with any the server sent along with a `304`.
+    pub fn updated(self, headers: &HeaderMap) -> Self {
+        let new = Self::from_headers(headers);
+        Self {
+            etag: new.etag.or(self.etag),
+            last_modified: new.last_modified.or(self.last_modified),
+        }
+    }
+}
+
+/// How long a cached entry counts as fresh.
+#[derive(Debug, Clone, Copy)]
+pub enum Freshness {
+    /// For this long after it was stored
+    For(Duration),
+    /// Until `end`, if it was stored after `start`: for data that stops
+    /// changing at `start`, such as a period that has ended. The expiry lets
+    /// entries that are no longer looked up be pruned.
+    Between {
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    },
+}
+
+impl Freshness {
+    fn is_fresh(self, stored: DateTime<Utc>) -> bool {
+        match self {
+            Self::For(duration) => Utc::now() - stored <= duration,
+            Self::Between { start, end } => stored >= start && Utc::now() < end,
+        }
+    }
+
+    fn expires(self, stored: DateTime<Utc>) -> DateTime<Utc> {
+        match self {
+            Self::For(duration) => stored + duration,
+            Self::Between { end, .. } => end,
+        }
+    }
+}
+
+/// How long the entries for a request stay fresh, and how long after that
+/// they may stand in for a refresh that fails.
+#[derive(Debug, Clone, Copy)]
+pub struct Policy {
+    pub freshness: Freshness,
+    pub stale: Duration,
+}
+
+impl Policy {
+    fn stale_until(self, stored: DateTime<Utc>) -> DateTime<Utc> {
+        self.freshness.expires(stored) + self.stale
+    }
+}
+
+/// An expired entry that is still of some use.
+pub struct Stale {
+    pub data: Value,
+    /// For the server to confirm that the entry is still current; empty if it
+    /// sent none
+    pub validators: Validators,
+    pub stored: DateTime<Utc>,
+    /// Whether the entry may stand in for a refresh that fails
+    pub fallback: bool,
+}
+
+pub enum Lookup {
+    Fresh(Value),
+    Stale(Stale),
+    Miss,
+}
+
+#[derive(Default)]
+struct Counters {
+    hits: AtomicUsize,
+    misses: AtomicUsize,
+    expired: AtomicUsize,
+    revalidated: AtomicUsize,
+    served_stale: AtomicUsize,
+    unusable: AtomicUsize,
 }
 
 #[derive(Clone)]
 pub struct Cache {
-    cache_dir: PathBuf,
-    expiry_hours: i64,
+    store: Arc<dyn CacheStore>,
+    expiry_hours: u32,
+    graphql_expiry_hours: u32,
+    stale_hours: u32,
+    policies: Arc<[CachePolicy]>,
+    counters: Arc<Counters>,
 }
 
 impl Cache {
-    pub fn new(cache_dir: &str, expiry_hours: i64) -> Self {
-        let cache_dir = PathBuf::from(cache_dir);
-        if !cache_dir.exists() {
-            fs::create_dir_all(&cache_dir).ok();
-        }
-
+    pub fn new(store: Arc<dyn CacheStore>, config: &CacheConfig) -> Self {
         Self {
-            cache_dir,
-            expiry_hours,
+            store,
+            expiry_hours: config.expiry_hours,
+            graphql_expiry_hours: config.graphql_expiry_hours,
+            stale_hours: config.stale_hours,
+            policies: config.policies.clone().into(),
+            counters: Arc::default(),
         }
     }
 
-    fn get_cache_key(key: &str) -> String {
-        let mut hasher = Sha256::new();
-        hasher.update(key.as_bytes());
-        hex::encode(hasher.finalize())
+    /// A cache in the store chosen by `[cache] backend`.
+    pub fn open(config: &CacheConfig) -> Result<Self> {
+        let store: Arc<dyn CacheStore> = match config.backend {
+            CacheBackend::Files => Arc::new(FileStore::new(&config.dir)),
+            CacheBackend::Sqlite => Arc::new(SqliteStore::open(&config.sqlite_path())?),
+            CacheBackend::Memory => Arc::new(MemoryStore::default()),
+        };
+        Ok(Self::new(store, config))
     }
 
-    fn get_cache_path(&self, key: &str) -> PathBuf {
-        let cache_key = Self::get_cache_key(key);
-        self.cache_dir.join(format!("{cache_key}.json"))
+    /// Where the entries live, for messages
+    pub fn location(&self) -> String {
+        self.store.location()
     }
 
-    pub fn get(&self, key: &str) -> Option<Value> {
-        let cache_path = self.get_cache_path(key);
+    /// The policy for the REST request of `path`.
+    pub fn rest_policy(&self, path: &str) -> Policy {
+        self.policy(path, self.expiry_hours)
+    }
 
-        if !cache_path.exists() {
-            return None;
+    /// The policy for GraphQL queries named `operation`.
+    pub fn graphql_policy(&self, operation: &str) -> Policy {
+        self.policy(operation, self.graphql_expiry_hours)
+    }
+
+    fn policy(&self, endpoint: &str, expiry_hours: u32) -> Policy {
+        let matching = self
+            .policies
+            .iter()
+            .find(|policy| glob_match(&policy.endpoint, endpoint));
+        let expiry_hours = matching
+            .and_then(|policy| policy.expiry_hours)
+            .unwrap_or(expiry_hours);
+        let stale_hours = matching
+            .and_then(|policy| policy.stale_hours)
+            .unwrap_or(self.stale_hours);
+
+        Policy {
+            freshness: Freshness::For(Duration::hours(i64::from(expiry_hours))),
+            stale: Duration::hours(i64::from(stale_hours)),
         }
+    }
+
+    /// Look up `key`. Expired entries are kept for revalidation as long as
+    /// they have validators, and as a fallback for the `stale` time of the
+    /// policy; the rest are removed.
+    pub fn lookup(&self, key: &str, policy: Policy) -> Lookup {
+        let lookup = self.read(key, policy);
+        let counter = match lookup {
+            Lookup::Fresh(_) => &self.counters.hits,
+            Lookup::Stale(..) => &self.counters.expired,
+            Lookup::Miss => &self.counters.misses,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        lookup
+    }
 
-        let contents = fs::read_to_string(&cache_path).ok()?;
-        let entry: CacheEntry = serde_json::from_str(&contents).ok()?;
+    fn read(&self, key: &str, policy: Policy) -> Lookup {
+        let contents = match self.store.get(key) {
+            Ok(Some(contents)) => contents,
+            Ok(None) => return Lookup::Miss,
+            Err(e) => {
+                println!("  [cache] {key}: {e:#}");
+                self.counters.unusable.fetch_add(1, Ordering::Relaxed);
+                return Lookup::Miss;
+            }
+        };
+        let entry = match parse_entry(&contents) {
+            Ok(entry) => entry,
+            Err(unusable) => {
+                println!("  [cache] {key}: {unusable}; fetching it again");
+                self.counters.unusable.fetch_add(1, Ordering::Relaxed);
+                if let Unusable::Corrupt(_) = unusable {
+                    self.store.remove(key).ok();
+                }
+                return Lookup::Miss;
+            }
+        };
 
         // Check if cache is expired
-        let age = Utc::now() - entry.timestamp;
-        if age > Duration::hours(self.expiry_hours) {
-            fs::remove_file(&cache_path).ok();
-            return None;
+        if policy.freshness.is_fresh(entry.timestamp) {
+            return Lookup::Fresh(entry.data);
+        }
+        let fallback = Utc::now() <= policy.stale_until(entry.timestamp);
+        if entry.validators.is_empty() && !fallback {
+            self.store.remove(key).ok();
+            return Lookup::Miss;
         }
+        Lookup::Stale(Stale {
+            data: entry.data,
+            validators: entry.validators,
+            stored: entry.timestamp,
+            fallback,
+        })
+    }
 
-        Some(entry.data)
+    /// Note that the server confirmed a stale entry is still current.
+    pub fn record_revalidated(&self) {
+        self.counters.revalidated.fetch_add(1, Ordering::Relaxed);
     }
 
-    pub fn set(&self, key: &str, value: &Value) -> Result<()> {
-        let cache_path = self.get_cache_path(key);
+    /// Note that a stale entry stood in for a refresh that failed.
+    pub fn record_served_stale(&self) {
+        self.counters.served_stale.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// How the lookups of this run went, if there were any.
+    pub fn summary(&self) -> Option<String> {
+        let hits = self.counters.hits.load(Ordering::Relaxed);
+        let misses = self.counters.misses.load(Ordering::Relaxed);
+        let expired = self.counters.expired.load(Ordering::Relaxed);
+        let revalidated = self.counters.revalidated.load(Ordering::Relaxed);
+        let served_stale = self.counters.served_stale.load(Ordering::Relaxed);
+        let unusable = self.counters.unusable.load(Ordering::Relaxed);
+        if hits + misses + expired == 0 {
+            return None;
+        }
+
+        let mut parts = vec![format!("{hits} hits"), format!("{misses} misses")];
+        if expired > 0 {
+            parts.push(format!(
+                "{revalidated} of {expired} expired entries still current"
+            ));
+        }
+        if served_stale > 0 {
+            parts.push(format!(
+                "{served_stale} stale entries served after failed refreshes"
+            ));
+        }
+        if unusable > 0 {
+            parts.push(format!("{unusable} unusable entries replaced"));
+        }
+        Some(parts.join(", "))
+    }
 
+    /// Store `value` under `key`, fresh according to `policy`.
+    pub fn set(
+        &self,
+        key: &str,
+        value: &Value,
+        validators: Validators,
+        policy: Policy,
+    ) -> Result<()> {
+        let timestamp = Utc::now();
         let entry = CacheEntry {
-            timestamp: Utc::now(),
+            version: CACHE_VERSION,
+            key: key.to_string(),
+            timestamp,
+            expires: Some(policy.freshness.expires(timestamp)),
+            stale_until: Some(policy.stale_until(timestamp)),
             data: value.clone(),
+            validators,
         };
 
         let contents = serde_json::to_string_pretty(&entry)?;
-        fs::write(cache_path, contents)?;
-        Ok(())
+        self.store.put(key, &contents)
+    }
+
+    /// Everything in the store, sorted by key and then by id.
+    pub fn entries(&self) -> Result<Vec<EntryInfo>> {
+        let now = Utc::now();
+        let mut entries: Vec<EntryInfo> = self
+            .store
+            .list()?
+            .into_iter()
+            .map(|stored| EntryInfo::new(stored, now))
+            .collect();
+
+        entries.sort_by(|a, b| a.key.cmp(&b.key).then_with(|| a.id().cmp(b.id())));
+        Ok(entries)
+    }
+
+    fn remove(&self, entries: impl IntoIterator<Item = EntryInfo>) -> Result<Removed> {
+        let mut removed = Removed::default();
+        for entry in entries {
+            self.store.delete(&entry.stored)?;
+            removed.files += 1;
+            removed.bytes += entry.stored.size;
+        }
+        Ok(removed)
+    }
+
+    /// Delete the entries that can no longer be served: expired ones that can
+    /// neither be revalidated nor stand in for a failed refresh, legacy,
+    /// corrupt and incompatible ones, files left by interrupted writes, and
+    /// with `older_than`, anything stored longer ago than that.
+    pub fn prune(&self, older_than: Option<Duration>) -> Result<Removed> {
+        let now = Utc::now();
+        let prunable = self.entries()?.into_iter().filter(|entry| {
+            matches!(
+                entry.status,
+                EntryStatus::Expired
+                    | EntryStatus::Legacy
+                    | EntryStatus::Corrupt
+                    | EntryStatus::Incompatible
+                    | EntryStatus::Partial
+            ) || older_than
+                .zip(entry.stored_at)
+                .is_some_and(|(age, stored)| now - stored > age)
+        });
+        self.remove(prunable)
+    }
+
+    /// Delete the cached responses whose key matches `pattern`, in which `*`
+    /// stands for any run of characters.
+    pub fn clear_matching(&self, pattern: &str) -> Result<Removed> {
+        let matching = self.entries()?.into_iter().filter(|entry| {
+            entry
+                .key
+                .as_deref()
+                .is_some_and(|key| glob_match(pattern, key))
+        });
+        self.remove(matching)
+    }
+}
+
+/// Write `contents` to `path` through a temporary file in the same directory
+/// and a rename, so that a run killed halfway leaves either the old file or
+/// the new one, never a truncated one.
+pub fn write_atomic(path: &Path, contents: &[u8]) -> Result<()> {
+    static TEMP_FILES: AtomicUsize = AtomicUsize::new(0);
+
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| anyhow!("{} is not a file path", path.display()))?;
+    let temp_path = path.with_file_name(format!(
+        ".{}.{}-{}.tmp",
+        file_name.to_string_lossy(),
+        process::id(),
+        TEMP_FILES.fetch_add(1, Ordering::Relaxed)
+    ));
+
+    let result = File::create(&temp_path)
+        .and_then(|mut file| {
+            file.write_all(contents)?;
+            file.sync_all()
+        })
+        .and_then(|()| fs::rename(&temp_path, path));
+    if result.is_err() {
+        fs::remove_file(&temp_path).ok();
+    }
+    result.with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// An item in the cache store.
+pub struct EntryInfo {
+    stored: Stored,
+    /// `None` for items that are not cached responses, such as the
+    /// contribution ratios, and for unreadable entries
+    pub key: Option<String>,
+    pub stored_at: Option<DateTime<Utc>>,
+    pub status: EntryStatus,
+}
+
+impl EntryInfo {
+    fn new(stored: Stored, now: DateTime<Utc>) -> Self {
+        let entry = match &stored.contents {
+            Contents::Entry(contents) => parse_entry(contents),
+            Contents::Partial => return Self::without_entry(stored, EntryStatus::Partial),
+            Contents::Other => return Self::without_entry(stored, EntryStatus::Other),
+        };
+
+        match entry {
+            Ok(entry) => {
+                let status = match entry.expires.filter(|_| !entry.key.is_empty()) {
+                    None => EntryStatus::Legacy,
+                    Some(expires) if expires > now => EntryStatus::Fresh,
+                    Some(_)
+                        if entry.validators.is_empty()
+                            && entry.stale_until.is_none_or(|until| until <= now) =>
+                    {
+                        EntryStatus::Expired
+                    }
+                    Some(_) => EntryStatus::Stale,
+                };
+                Self {
+                    stored,
+                    key: Some(entry.key).filter(|key| !key.is_empty()),
+                    stored_at: Some(entry.timestamp),
+                    status,
+                }
+            }
+            Err(Unusable::Corrupt(_)) => Self::without_entry(stored, EntryStatus::Corrupt),
+            Err(Unusable::Incompatible(_)) => {
+                Self::without_entry(stored, EntryStatus::Incompatible)
+            }
+        }
+    }
+
+    fn without_entry(stored: Stored, status: EntryStatus) -> Self {
+        Self {
+            stored,
+            key: None,
+            stored_at: None,
+            status,
+        }
+    }
+
+    /// The file name, or the key in stores that index entries by key
+    pub fn id(&self) -> &str {
+        &self.stored.id
+    }
+
+    pub fn size(&self) -> u64 {
+        self.stored.size
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryStatus {
+    Fresh,
+    /// Expired, but can be revalidated with the server or stand in for a
+    /// refresh that fails
+    Stale,
+    Expired,
+    /// Written before entries recorded their key and expiry
+    Legacy,
+    /// An entry that cannot be parsed
+    Corrupt,
+    /// Written in another format version
+    Incompatible,
+    /// Left behind by an interrupted write
+    Partial,
+    /// Not a cached response
+    Other,
+}
+
+impl EntryStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Fresh => "fresh",
+            Self::Stale => "stale",
+            Self::Expired => "expired",
+            Self::Legacy => "legacy",
+            Self::Corrupt => "corrupt",
+            Self::Incompatible => "incompatible",
+            Self::Partial => "partial",
+            Self::Other => "other",
+        }
+    }
+}
+
+/// Files and bytes deleted from the cache.
+#[derive(Debug, Default)]
+pub struct Removed {
+    pub files: usize,
+    pub bytes: u64,
+}
+
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = text.strip_prefix(first) else {
+        return false;
+    };
+
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false,
+        }
     }
+    rest.len() >= last.len() && rest.ends_with(last)
 }
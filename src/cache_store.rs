@@ -0,0 +1,298 @@
+//! Where cached responses are kept.
+//!
+//! `Cache` decides what goes into an entry and whether it is still fresh; a
+//! `CacheStore` only keeps the serialized entries under their keys. The store
+//! is chosen with `[cache] backend`.
+
+use anyhow::{Context, Result};
+use rusqlite::{params, Connection, OptionalExtension};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    sync::{Mutex, MutexGuard},
+    time::Duration,
+};
+
+use crate::cache::write_atomic;
+
+pub trait CacheStore: Send + Sync {
+    /// The directory or file the entries live in, for messages
+    fn location(&self) -> String;
+
+    /// The entry stored under `key`, if any.
+    fn get(&self, key: &str) -> Result<Option<String>>;
+
+    /// Store `entry` under `key`, replacing any entry already there.
+    fn put(&self, key: &str, entry: &str) -> Result<()>;
+
+    fn remove(&self, key: &str) -> Result<()>;
+
+    /// Everything in the store, including what is not an entry.
+    fn list(&self) -> Result<Vec<Stored>>;
+
+    /// Delete something returned by [`Self::list`].
+    fn delete(&self, stored: &Stored) -> Result<()>;
+}
+
+/// An item in a store.
+pub struct Stored {
+    /// The file name, or the key in stores that index entries by key
+    pub id: String,
+    pub contents: Contents,
+    pub size: u64,
+}
+
+pub enum Contents {
+    Entry(String),
+    /// Left behind by an interrupted write
+    Partial,
+    /// Not a cached response, such as the contribution ratios
+    Other,
+}
+
+/// One JSON file per entry, named after the hash of its key.
+pub struct FileStore {
+    dir: PathBuf,
+}
+
+impl FileStore {
+    pub fn new(dir: &Path) -> Self {
+        if !dir.exists() {
+            fs::create_dir_all(dir).ok();
+        }
+        Self {
+            dir: dir.to_path_buf(),
+        }
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        let mut hasher = Sha256::new();
+        hasher.update(key.as_bytes());
+        self.dir
+            .join(format!("{}.json", hex::encode(hasher.finalize())))
+    }
+}
+
+impl CacheStore for FileStore {
+    fn location(&self) -> String {
+        self.dir.display().to_string()
+    }
+
+    fn get(&self, key: &str) -> Result<Option<String>> {
+        let path = self.path(key);
+        match fs::read_to_string(&path) {
+            Ok(contents) => Ok(Some(contents)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e).with_context(|| format!("cannot read {}", path.display())),
+        }
+    }
+
+    fn put(&self, key: &str, entry: &str) -> Result<()> {
+        write_atomic(&self.path(key), entry.as_bytes())
+    }
+
+    fn remove(&self, key: &str) -> Result<()> {
+        let path = self.path(key);
+        match fs::remove_file(&path) {
+            Err(e) if e.kind() != ErrorKind::NotFound => {
+                Err(e).with_context(|| format!("Failed to delete {}", path.display()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn list(&self) -> Result<Vec<Stored>> {
+        let read_dir = match fs::read_dir(&self.dir) {
+            Ok(read_dir) => read_dir,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => {
+                return Err(e).with_context(|| format!("Failed to read {}", self.dir.display()))
+            }
+        };
+
+        let mut stored = Vec::new();
+        for dir_entry in read_dir {
+            let path = dir_entry?.path();
+            if !path.is_file() {
+                continue;
+            }
+            let size = fs::metadata(&path)?.len();
+
+            let hashed = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .is_some_and(|stem| {
+                    stem.len() == 64 && stem.chars().all(|c| c.is_ascii_hexdigit())
+                });
+            let contents = if hashed {
+                Contents::Entry(fs::read_to_string(&path)?)
+            } else if path.extension().is_some_and(|extension| extension == "tmp") {
+                Contents::Partial
+            } else {
+                Contents::Other
+            };
+
+            stored.push(Stored {
+                id: path
+                    .file_name()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .into(),
+                contents,
+                size,
+            });
+        }
+        Ok(stored)
+    }
+
+    fn delete(&self, stored: &Stored) -> Result<()> {
+        let path = self.dir.join(&stored.id);
+        fs::remove_file(&path).with_context(|| format!("Failed to delete {}", path.display()))
+    }
+}
+
+/// Entries kept for the length of the run only.
+#[derive(Default)]
+pub struct MemoryStore {
+    entries: Mutex<BTreeMap<String, String>>,
+}
+
+impl MemoryStore {
+    fn entries(&self) -> MutexGuard<'_, BTreeMap<String, String>> {
+        self.entries.lock().expect("memory cache poisoned")
+    }
+}
+
+impl CacheStore for MemoryStore {
+    fn location(&self) -> String {
+        "memory".to_string()
+    }
+
+    fn get(&self, key: &str) -> Result<Option<String>> {
+        Ok(self.entries().get(key).cloned())
+    }
+
+    fn put(&self, key: &str, entry: &str) -> Result<()> {
+        self.entries().insert(key.to_string(), entry.to_string());
+        Ok(())
+    }
+
+    fn remove(&self, key: &str) -> Result<()> {
+        self.entries().remove(key);
+        Ok(())
+    }
+
+    fn list(&self) -> Result<Vec<Stored>> {
+        Ok(self
+            .entries()
+            .iter()
+            .map(|(key, entry)| Stored {
+                id: key.clone(),
+                contents: Contents::Entry(entry.clone()),
+                size: entry.len() as u64,
+            })
+            .collect())
+    }
+
+    fn delete(&self, stored: &Stored) -> Result<()> {
+        self.remove(&stored.id)
+    }
+}
+
+/// Every entry in one `sqlite` database file, which restores much faster through
+/// the Actions cache than thousands of small files.
+pub struct SqliteStore {
+    path: PathBuf,
+    connection: Mutex<Connection>,
+}
+
+impl SqliteStore {
+    /// Open the database at `path`, creating it if needed.
+    pub fn open(path: &Path) -> Result<Self> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+        let connection = Connection::open(path)
+            .and_then(|connection| {
+                // Collections for several hosts share the file
+                connection.busy_timeout(Duration::from_secs(10))?;
+                connection.execute(
+                    "CREATE TABLE IF NOT EXISTS entries (key TEXT PRIMARY KEY, entry TEXT NOT NULL)",
+                    [],
+                )?;
+                Ok(connection)
+            })
+            .with_context(|| format!("Failed to open {}", path.display()))?;
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            connection: Mutex::new(connection),
+        })
+    }
+
+    fn connection(&self) -> MutexGuard<'_, Connection> {
+        self.connection.lock().expect("sqlite connection poisoned")
+    }
+}
+
+impl CacheStore for SqliteStore {
+    fn location(&self) -> String {
+        self.path.display().to_string()
+    }
+
+    fn get(&self, key: &str) -> Result<Option<String>> {
+        self.connection()
+            .query_row(
+                "SELECT entry FROM entries WHERE key = ?1",
+                params![key],
+                |row| row.get(0),
+            )
+            .optional()
+            .with_context(|| format!("cannot read {}", self.path.display()))
+    }
+
+    fn put(&self, key: &str, entry: &str) -> Result<()> {
+        self.connection()
+            .execute(
+                "INSERT OR REPLACE INTO entries (key, entry) VALUES (?1, ?2)",
+                params![key, entry],
+            )
+            .with_context(|| format!("Failed to write {}", self.path.display()))?;
+        Ok(())
+    }
+
+    fn remove(&self, key: &str) -> Result<()> {
+        self.connection()
+            .execute("DELETE FROM entries WHERE key = ?1", params![key])
+            .with_context(|| format!("Failed to write {}", self.path.display()))?;
+        Ok(())
+    }
+
+    fn list(&self) -> Result<Vec<Stored>> {
+        let connection = self.connection();
+        let mut statement = connection.prepare("SELECT key, entry FROM entries")?;
+        let rows = statement.query_map([], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })?;
+
+        let mut stored = Vec::new();
+        for row in rows {
+            let (key, entry) =
+                row.with_context(|| format!("cannot read {}", self.path.display()))?;
+            stored.push(Stored {
+                id: key,
+                size: entry.len() as u64,
+                contents: Contents::Entry(entry),
+            });
+        }
+        Ok(stored)
+    }
+
+    fn delete(&self, stored: &Stored) -> Result<()> {
+        self.remove(&stored.id)
+    }
+}
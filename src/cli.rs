@@ -0,0 +1,222 @@
+use anyhow::{anyhow, Result};
+use clap::{Args, Parser, Subcommand};
+use std::{env, path::PathBuf};
+
+use crate::config::CacheBackend;
+
+/// Generate GitHub statistics cards for a profile README.
+///
+/// Settings are read from github-stats.toml when it exists. Command-line flags
+/// take precedence over environment variables, which take precedence over the
+/// configuration file. The environment variables are listed in each flag's help
+/// text and keep the GitHub Actions workflow working unchanged.
+#[derive(Parser)]
+#[command(version, about)]
+pub struct Cli {
+    /// Configuration file to load [default: github-stats.toml, if present]
+    #[arg(long, global = true, env = "GITHUB_STATS_CONFIG")]
+    pub config: Option<PathBuf>,
+
+    /// Directory for cached API responses
+    #[arg(long, global = true, env = "GITHUB_STATS_CACHE_DIR")]
+    pub cache_dir: Option<PathBuf>,
+
+    /// How cached API responses are stored [default: files]
+    #[arg(long, global = true, env = "GITHUB_STATS_CACHE_BACKEND", value_enum)]
+    pub cache_backend: Option<CacheBackend>,
+
+    /// JSON snapshot written by `collect` and read by `render`
+    #[arg(long, global = true, env = "GITHUB_STATS_SNAPSHOT")]
+    pub snapshot: Option<PathBuf>,
+
+    /// JSONL file that every collected snapshot is appended to
+    #[arg(long, global = true, env = "GITHUB_STATS_HISTORY")]
+    pub history: Option<PathBuf>,
+
+    /// JSON file that the daily views of every repository are merged into
+    #[arg(long, global = true, env = "GITHUB_STATS_TRAFFIC_HISTORY")]
+    pub traffic_history: Option<PathBuf>,
+
+    /// Save every API request and response to this fixtures directory
+    #[arg(
+        long,
+        global = true,
+        env = "GITHUB_STATS_RECORD",
+        conflicts_with = "replay"
+    )]
+    pub record: Option<PathBuf>,
+
+    /// Serve API responses from this fixtures directory instead of the network
+    #[arg(long, global = true, env = "GITHUB_STATS_REPLAY")]
+    pub replay: Option<PathBuf>,
+
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Fetch statistics from GitHub and write them to the JSON snapshot
+    Collect(CollectArgs),
+    /// Render the SVG cards from the JSON snapshot without any network access
+    Render,
+    /// Summarize how the statistics changed across recorded runs
+    History(HistoryArgs),
+    /// Manage the local API response cache
+    Cache {
+        #[command(subcommand)]
+        action: CacheAction,
+    },
+    /// Check that the token, templates and cache directory are usable
+    Doctor(GitHubArgs),
+}
+
+#[derive(Subcommand)]
+pub enum CacheAction {
+    /// List cached API responses with their keys, ages and sizes
+    Ls,
+    /// Delete expired responses that cannot be revalidated, and unreadable ones
+    ///
+    /// Responses with an `ETag` or Last-Modified date can always be revalidated,
+    /// so they are only deleted with --older-than.
+    Prune {
+        /// Also delete responses stored more than this many days ago
+        #[arg(long, value_name = "DAYS")]
+        older_than: Option<u32>,
+    },
+    /// Delete every cached API response and the cached contribution ratios
+    Clear {
+        /// Only delete responses whose key matches this pattern, in which `*`
+        /// stands for any run of characters, e.g. '*/traffic/*'
+        #[arg(long)]
+        pattern: Option<String>,
+    },
+}
+
+#[derive(Args)]
+pub struct HistoryArgs {
+    /// Number of days to compare against the latest run
+    #[arg(long, default_value_t = 30)]
+    pub days: u32,
+
+    /// Also print this language's share of code at every recorded run
+    #[arg(long)]
+    pub language: Option<String>,
+}
+
+#[derive(Args)]
+pub struct GitHubArgs {
+    /// GitHub personal access token (falls back to the Actions-provided token)
+    #[arg(long, env = "ACCESS_TOKEN", hide_env_values = true)]
+    pub token: Option<String>,
+
+    /// GitHub username to collect statistics for
+    #[arg(long, short, env = "GITHUB_ACTOR")]
+    pub user: Option<String>,
+
+    /// REST API root, such as the /api/v3 path of an Enterprise Server host
+    /// [default: api.github.com]
+    #[arg(long, env = "GITHUB_STATS_API_URL")]
+    pub api_url: Option<String>,
+
+    /// GraphQL endpoint [default: derived from the REST API root]
+    #[arg(long, env = "GITHUB_STATS_GRAPHQL_URL")]
+    pub graphql_url: Option<String>,
+}
+
+impl GitHubArgs {
+    pub fn token(&self) -> Result<String> {
+        let non_empty = |t: &String| !t.trim().is_empty();
+        self.token
+            .clone()
+            .filter(non_empty)
+            .or_else(|| env::var("GITHUB_TOKEN").ok().filter(non_empty))
+            .ok_or_else(|| {
+                anyhow!("no access token given; pass --token or set ACCESS_TOKEN or GITHUB_TOKEN")
+            })
+    }
+}
+
+#[derive(Args)]
+pub struct CollectArgs {
+    #[command(flatten)]
+    pub github: GitHubArgs,
+
+    /// Repositories to skip, in owner/name format (comma-separated)
+    #[arg(long = "exclude-repo", env = "EXCLUDED", value_delimiter = ',')]
+    pub excluded_repos: Vec<String>,
+
+    /// Languages to skip, case-insensitive (comma-separated); HTML is always skipped
+    #[arg(long = "exclude-lang", env = "EXCLUDED_LANGS", value_delimiter = ',')]
+    pub excluded_langs: Vec<String>,
+
+    /// Only count owned repositories, not repositories contributed to
+    #[arg(
+        long,
+        env = "EXCLUDE_FORKED_REPOS",
+        num_args = 0..=1,
+        default_missing_value = "true",
+        value_parser = parse_forked
+    )]
+    pub exclude_forked: Option<Toggle>,
+
+    /// Fail the run when GitHub returns partial data, e.g. repositories hidden
+    /// by SAML single sign-on, instead of skipping it
+    #[arg(
+        long,
+        env = "GITHUB_STATS_STRICT",
+        num_args = 0..=1,
+        default_missing_value = "true",
+        value_parser = parse_bool
+    )]
+    pub strict: Option<Toggle>,
+
+    /// Cache GraphQL responses too; contributions of past years are then
+    /// fetched only once a year
+    #[arg(
+        long,
+        env = "GITHUB_STATS_CACHE_GRAPHQL",
+        num_args = 0..=1,
+        default_missing_value = "true",
+        value_parser = parse_bool
+    )]
+    pub cache_graphql: Option<Toggle>,
+
+    /// Maximum number of concurrent API requests
+    #[arg(long, env = "GITHUB_STATS_CONCURRENCY")]
+    pub concurrency: Option<usize>,
+}
+
+/// Trim comma-separated values and drop empty ones, so that an unset Actions
+/// secret (which expands to an empty string) falls through to the config file.
+pub fn clean_list(values: &[String]) -> Vec<String> {
+    values
+        .iter()
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+        .collect()
+}
+
+/// A boolean flag value, or `None` when it was given empty. Spelled as an alias
+/// so that clap does not read `Option<Option<bool>>` as a flag with an
+/// optional value.
+pub type Toggle = Option<bool>;
+
+/// Parse a boolean flag value. An empty string, which is what an unset
+/// workflow variable expands to, parses as `None` and falls through to the
+/// config file like a flag that was not given.
+fn parse_bool(value: &str) -> Result<Toggle, String> {
+    match value.trim().to_lowercase().as_str() {
+        "" => Ok(None),
+        "false" | "no" | "off" | "0" => Ok(Some(false)),
+        "true" | "yes" | "on" | "1" => Ok(Some(true)),
+        other => Err(format!("expected true or false, got {other:?}")),
+    }
+}
+
+/// Parse `--exclude-forked`, which like the original workflow variable treats
+/// any value it does not recognise as true.
+#[allow(clippy::unnecessary_wraps)]
+fn parse_forked(value: &str) -> Result<Toggle, String> {
+    Ok(parse_bool(value).unwrap_or(Some(true)))
+}
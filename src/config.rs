@@ -0,0 +1,583 @@
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::NaiveDate;
+use clap::ValueEnum;
+use serde::Deserialize;
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    cli::{clean_list, Cli, CollectArgs, GitHubArgs},
+    fixtures::FixtureMode,
+};
+
+pub const DEFAULT_CONFIG_PATH: &str = "github-stats.toml";
+pub const DEFAULT_API_URL: &str = "https://api.github.com";
+
+/// Settings loaded from `github-stats.toml`.
+///
+/// Every value has a built-in default, so the file and each of its tables are
+/// optional. Values are resolved in this order, highest priority first:
+///
+/// 1. command-line flags
+/// 2. environment variables
+/// 3. the configuration file
+/// 4. built-in defaults
+///
+/// The access token is deliberately not configurable here so that it never
+/// ends up committed to a repository.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Where `collect` writes its JSON snapshot and `render` reads it from.
+    pub snapshot: PathBuf,
+    pub github: GitHubConfig,
+    /// Additional GitHub hosts, such as Enterprise Server instances, whose
+    /// statistics are added to those of the main host.
+    pub hosts: Vec<HostConfig>,
+    pub collect: CollectConfig,
+    pub cache: CacheConfig,
+    pub retry: RetryConfig,
+    pub history: HistoryConfig,
+    pub traffic: TrafficConfig,
+    pub render: RenderConfig,
+    /// Set from the command line only
+    #[serde(skip)]
+    pub fixtures: Option<FixtureMode>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            snapshot: PathBuf::from("generated/stats.json"),
+            github: GitHubConfig::default(),
+            hosts: Vec::new(),
+            collect: CollectConfig::default(),
+            cache: CacheConfig::default(),
+            retry: RetryConfig::default(),
+            history: HistoryConfig::default(),
+            traffic: TrafficConfig::default(),
+            render: RenderConfig::default(),
+            fixtures: None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GitHubConfig {
+    pub user: Option<String>,
+    /// REST API root, e.g. `https://github.example.com/api/v3` for Enterprise
+    pub api_url: String,
+    /// GraphQL endpoint; derived from `api_url` when unset
+    pub graphql_url: Option<String>,
+}
+
+impl Default for GitHubConfig {
+    fn default() -> Self {
+        Self {
+            user: None,
+            api_url: DEFAULT_API_URL.to_string(),
+            graphql_url: None,
+        }
+    }
+}
+
+/// An extra host to collect from, authenticated with its own token.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HostConfig {
+    /// Short label used in log output and cache file names, made of letters,
+    /// digits, `.`, `_` and `-`
+    pub name: String,
+    pub api_url: String,
+    pub graphql_url: Option<String>,
+    /// Account on this host; defaults to `github.user`
+    pub user: Option<String>,
+    /// Environment variable holding this host's access token
+    pub token_env: String,
+}
+
+/// A host with its endpoints, account and token fully resolved.
+pub struct Host {
+    pub name: String,
+    pub api_url: String,
+    pub graphql_url: String,
+    pub user: String,
+    pub token: String,
+    /// Whether this is the `[github]` host rather than one of `[[hosts]]`
+    pub primary: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CollectConfig {
+    pub exclude_repos: Vec<String>,
+    pub exclude_langs: Vec<String>,
+    pub exclude_forked: bool,
+    /// Fail instead of skipping data GitHub could not return
+    pub strict: bool,
+    pub concurrency: usize,
+}
+
+impl Default for CollectConfig {
+    fn default() -> Self {
+        Self {
+            exclude_repos: Vec::new(),
+            exclude_langs: Vec::new(),
+            exclude_forked: false,
+            strict: false,
+            concurrency: 25,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CacheConfig {
+    pub dir: PathBuf,
+    pub backend: CacheBackend,
+    pub expiry_hours: u32,
+    /// Cache GraphQL responses as well as REST ones
+    pub graphql: bool,
+    /// How long cached GraphQL responses stay valid. Contributions of past
+    /// years do not expire.
+    pub graphql_expiry_hours: u32,
+    /// How long after expiring a response may still stand in for a refresh
+    /// that fails
+    pub stale_hours: u32,
+    /// Expiry overrides for particular endpoints; the first match applies
+    pub policies: Vec<CachePolicy>,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            dir: PathBuf::from(".github_stats_cache"),
+            backend: CacheBackend::Files,
+            expiry_hours: 6,
+            graphql: false,
+            graphql_expiry_hours: 1,
+            stale_hours: 168,
+            policies: Vec::new(),
+        }
+    }
+}
+
+impl CacheConfig {
+    /// The database of the `sqlite` backend
+    pub fn sqlite_path(&self) -> PathBuf {
+        self.dir.join("cache.sqlite")
+    }
+}
+
+/// Cache settings for the requests matching `endpoint`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CachePolicy {
+    /// A REST path such as `*/traffic/views` or a GraphQL operation name such
+    /// as `Contributions`, in which `*` stands for any run of characters
+    pub endpoint: String,
+    /// Defaults to `expiry_hours`, or `graphql_expiry_hours` for GraphQL
+    pub expiry_hours: Option<u32>,
+    /// Defaults to `stale_hours`
+    pub stale_hours: Option<u32>,
+}
+
+/// Where cached API responses are kept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum CacheBackend {
+    /// One JSON file per response in the cache directory
+    Files,
+    /// A single `sqlite` database in the cache directory
+    Sqlite,
+    /// Nothing kept beyond the run
+    Memory,
+}
+
+/// Retries for requests that fail with a 5xx status, a dropped connection, a
+/// timeout or a retryable GraphQL error, and while GitHub is still computing
+/// repository statistics (202).
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetryConfig {
+    /// Total attempts per request, including the first
+    pub max_attempts: u32,
+    /// Delay before the first retry; it doubles with every further retry
+    pub initial_delay_ms: u64,
+    pub max_delay_secs: u64,
+    /// No retry is started once this much time has passed since the first attempt
+    pub deadline_secs: u64,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: 8,
+            initial_delay_ms: 1000,
+            max_delay_secs: 30,
+            deadline_secs: 120,
+        }
+    }
+}
+
+/// Run history kept across collections. It lives outside the cache directory so
+/// that `cache clear` never throws it away.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HistoryConfig {
+    pub enabled: bool,
+    pub path: PathBuf,
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            path: PathBuf::from("generated/history.jsonl"),
+        }
+    }
+}
+
+/// Daily repository views, kept beyond the 14 days GitHub reports. Like the
+/// run history it lives outside the cache directory.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TrafficConfig {
+    pub enabled: bool,
+    pub path: PathBuf,
+}
+
+impl Default for TrafficConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            path: PathBuf::from("generated/traffic.json"),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RenderConfig {
+    pub max_languages: usize,
+    /// Window, in days, for the "+12 this week" deltas on the overview card.
+    pub delta_days: u32,
+    /// Window, in days, drawn by the sparklines on the trends card.
+    pub trend_days: u32,
+    /// Year shown on the calendar card, or the last 12 months when unset.
+    pub calendar_year: Option<i32>,
+    pub overview: CardConfig,
+    pub languages: CardConfig,
+    pub trends: CardConfig,
+    pub calendar: CardConfig,
+    pub streaks: CardConfig,
+    pub traffic: CardConfig,
+}
+
+impl Default for RenderConfig {
+    fn default() -> Self {
+        Self {
+            max_languages: 12,
+            delta_days: 7,
+            trend_days: 90,
+            calendar_year: None,
+            overview: CardConfig::named("overview"),
+            languages: CardConfig::named("languages"),
+            trends: CardConfig::named("trends"),
+            calendar: CardConfig::named("calendar"),
+            streaks: CardConfig::named("streaks"),
+            traffic: CardConfig::named("traffic"),
+        }
+    }
+}
+
+impl RenderConfig {
+    pub fn cards(&self) -> [&CardConfig; 6] {
+        [
+            &self.overview,
+            &self.languages,
+            &self.trends,
+            &self.calendar,
+            &self.streaks,
+            &self.traffic,
+        ]
+    }
+}
+
+/// Where a card's template is read from and where the rendered SVG goes.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CardConfig {
+    pub template: PathBuf,
+    pub output: PathBuf,
+}
+
+impl CardConfig {
+    fn named(card: &str) -> Self {
+        Self {
+            template: PathBuf::from(format!("templates/{card}.svg")),
+            output: PathBuf::from(format!("generated/{card}.svg")),
+        }
+    }
+}
+
+impl Config {
+    /// Load the configuration file.
+    ///
+    /// An explicitly requested file must exist. When no path is given,
+    /// `github-stats.toml` in the working directory is used if present and
+    /// the defaults otherwise.
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        let (path, required) = match path {
+            Some(path) => (path, true),
+            None => (Path::new(DEFAULT_CONFIG_PATH), false),
+        };
+
+        if !required && !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file {}", path.display()))?;
+        let config: Self = toml::from_str(&contents)
+            .with_context(|| format!("Invalid config file {}", path.display()))?;
+        config
+            .validate()
+            .with_context(|| format!("Invalid config file {}", path.display()))?;
+
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self
+            .github
+            .user
+            .as_deref()
+            .is_some_and(|u| u.trim().is_empty())
+        {
+            bail!("github.user must not be empty");
+        }
+        validate_url("github.api_url", &self.github.api_url)?;
+        if let Some(url) = &self.github.graphql_url {
+            validate_url("github.graphql_url", url)?;
+        }
+        for (i, host) in self.hosts.iter().enumerate() {
+            if host.name.trim().is_empty() {
+                bail!("hosts[{i}].name must not be empty");
+            }
+            // The name becomes part of file and directory names
+            if host.name.starts_with('.')
+                || !host
+                    .name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'))
+            {
+                bail!(
+                    "hosts[{i}].name {:?} may only contain letters, digits, '.', '_' and '-', \
+                     and must not start with '.'",
+                    host.name
+                );
+            }
+            if self.hosts[..i].iter().any(|h| h.name == host.name) {
+                bail!("hosts[{i}].name {:?} is used more than once", host.name);
+            }
+            validate_url(&format!("hosts[{i}].api_url"), &host.api_url)?;
+            if let Some(url) = &host.graphql_url {
+                validate_url(&format!("hosts[{i}].graphql_url"), url)?;
+            }
+        }
+        for (i, policy) in self.cache.policies.iter().enumerate() {
+            if policy.endpoint.trim().is_empty() {
+                bail!("cache.policies[{i}].endpoint must not be empty");
+            }
+        }
+        if self.collect.concurrency == 0 {
+            bail!("collect.concurrency must be at least 1");
+        }
+        if self.retry.max_attempts == 0 {
+            bail!("retry.max_attempts must be at least 1");
+        }
+        if self.retry.max_delay_secs == 0 || self.retry.deadline_secs == 0 {
+            bail!("retry.max_delay_secs and retry.deadline_secs must be at least 1");
+        }
+        if self.render.max_languages == 0 {
+            bail!("render.max_languages must be at least 1");
+        }
+        if self.render.delta_days == 0 {
+            bail!("render.delta_days must be at least 1");
+        }
+        if self.render.trend_days == 0 {
+            bail!("render.trend_days must be at least 1");
+        }
+        if self
+            .render
+            .calendar_year
+            .is_some_and(|year| NaiveDate::from_ymd_opt(year, 1, 1).is_none() || year < 2008)
+        {
+            bail!("render.calendar_year must be a year since GitHub launched in 2008");
+        }
+        Ok(())
+    }
+
+    /// Override file settings with any values given on the command line or
+    /// through the environment.
+    pub fn apply_global_args(&mut self, cli: &Cli) {
+        if let Some(dir) = cli.cache_dir.clone() {
+            self.cache.dir = dir;
+        }
+        if let Some(backend) = cli.cache_backend {
+            self.cache.backend = backend;
+        }
+        if let Some(snapshot) = cli.snapshot.clone() {
+            self.snapshot = snapshot;
+        }
+        if let Some(history) = cli.history.clone() {
+            self.history.path = history;
+        }
+        if let Some(traffic) = cli.traffic_history.clone() {
+            self.traffic.path = traffic;
+        }
+        if let Some(dir) = cli.record.clone() {
+            self.fixtures = Some(FixtureMode::Record(dir));
+        }
+        if let Some(dir) = cli.replay.clone() {
+            self.fixtures = Some(FixtureMode::Replay(dir));
+        }
+    }
+
+    pub fn apply_github_args(&mut self, args: &GitHubArgs) {
+        if let Some(user) = args.user.clone().filter(|u| !u.trim().is_empty()) {
+            self.github.user = Some(user);
+        }
+        if let Some(url) = args.api_url.clone().filter(|u| !u.trim().is_empty()) {
+            self.github.api_url = url;
+        }
+        if let Some(url) = args.graphql_url.clone().filter(|u| !u.trim().is_empty()) {
+            self.github.graphql_url = Some(url);
+        }
+    }
+
+    pub fn apply_collect_args(&mut self, args: &CollectArgs) -> Result<()> {
+        self.apply_github_args(&args.github);
+
+        let excluded_repos = clean_list(&args.excluded_repos);
+        if !excluded_repos.is_empty() {
+            self.collect.exclude_repos = excluded_repos;
+        }
+
+        let excluded_langs = clean_list(&args.excluded_langs);
+        if !excluded_langs.is_empty() {
+            self.collect.exclude_langs = excluded_langs;
+        }
+
+        if let Some(Some(exclude_forked)) = args.exclude_forked {
+            self.collect.exclude_forked = exclude_forked;
+        }
+
+        if let Some(Some(strict)) = args.strict {
+            self.collect.strict = strict;
+        }
+
+        if let Some(Some(cache_graphql)) = args.cache_graphql {
+            self.cache.graphql = cache_graphql;
+        }
+
+        if let Some(concurrency) = args.concurrency {
+            self.collect.concurrency = concurrency;
+        }
+
+        self.validate()
+    }
+
+    pub fn user(&self) -> Result<&str> {
+        self.github.user.as_deref().ok_or_else(|| {
+            anyhow!("no username given; pass --user, set GITHUB_ACTOR or set github.user in {DEFAULT_CONFIG_PATH}")
+        })
+    }
+
+    /// Resolve the main host and every `[[hosts]]` entry, reading each extra
+    /// host's token from its environment variable.
+    pub fn hosts(&self, primary_token: String) -> Result<Vec<Host>> {
+        let user = self.user()?;
+        let mut hosts = vec![Host {
+            name: host_name(&self.github.api_url),
+            api_url: trim_url(&self.github.api_url),
+            graphql_url: self
+                .github
+                .graphql_url
+                .as_deref()
+                .map_or_else(|| default_graphql_url(&self.github.api_url), trim_url),
+            user: user.to_string(),
+            token: primary_token,
+            primary: true,
+        }];
+
+        for host in &self.hosts {
+            let token = env::var(&host.token_env)
+                .ok()
+                .filter(|t| !t.trim().is_empty())
+                .ok_or_else(|| {
+                    anyhow!(
+                        "no access token for host {}; set the {} environment variable",
+                        host.name,
+                        host.token_env
+                    )
+                })?;
+
+            hosts.push(Host {
+                name: host.name.clone(),
+                api_url: trim_url(&host.api_url),
+                graphql_url: host
+                    .graphql_url
+                    .as_deref()
+                    .map_or_else(|| default_graphql_url(&host.api_url), trim_url),
+                user: host.user.as_deref().unwrap_or(user).to_string(),
+                token,
+                primary: false,
+            });
+        }
+
+        Ok(hosts)
+    }
+
+    pub fn ratio_cache_path(&self, host: &Host) -> PathBuf {
+        if host.primary {
+            self.cache.dir.join("ratio_cache.json")
+        } else {
+            self.cache
+                .dir
+                .join(format!("ratio_cache-{}.json", host.name))
+        }
+    }
+}
+
+fn validate_url(key: &str, url: &str) -> Result<()> {
+    if !(url.starts_with("https://") || url.starts_with("http://")) {
+        bail!("{key} must be an http(s) URL, got {url:?}");
+    }
+    Ok(())
+}
+
+fn trim_url(url: &str) -> String {
+    url.trim_end_matches('/').to_string()
+}
+
+/// GitHub.com serves GraphQL at `/graphql` under the API root, while
+/// Enterprise Server serves it at `/api/graphql` next to `/api/v3`.
+fn default_graphql_url(api_url: &str) -> String {
+    let api_url = api_url.trim_end_matches('/');
+    match api_url.strip_suffix("/api/v3") {
+        Some(root) => format!("{root}/api/graphql"),
+        None => format!("{api_url}/graphql"),
+    }
+}
+
+/// Label a host by its domain, e.g. `api.github.com` becomes `github.com`.
+fn host_name(api_url: &str) -> String {
+    let without_scheme = api_url.split("://").nth(1).unwrap_or(api_url);
+    let domain = without_scheme.split('/').next().unwrap_or(without_scheme);
+    domain.strip_prefix("api.").unwrap_or(domain).to_string()
+}
@@ -0,0 +1,119 @@
+use anyhow::{anyhow, Result};
+use std::{fs, path::Path};
+
+use crate::{
+    cache::Cache,
+    cli::GitHubArgs,
+    config::{Config, Host},
+    fixtures::Fixtures,
+    github_client::GitHubClient,
+    retry::RetryPolicy,
+};
+
+/// Run every environment check and print one line per check.
+///
+/// Returns `true` when all checks passed.
+pub async fn run(args: &GitHubArgs, config: &Config) -> bool {
+    let mut healthy = true;
+
+    let user = report(
+        "username",
+        config.user().map(|u| format!("collecting for {u}")),
+    );
+    healthy &= user.is_some();
+
+    match args.token() {
+        Ok(token) => {
+            report("access token", Ok("found".to_string()));
+            let hosts = match config.hosts(token) {
+                Ok(hosts) => hosts,
+                Err(e) => {
+                    report::<String>("hosts", Err(e));
+                    return false;
+                }
+            };
+            let names: Vec<_> = hosts.iter().map(|h| h.name.as_str()).collect();
+            report("hosts", Ok(names.join(", ")));
+
+            for host in &hosts {
+                let login = report(
+                    &format!("token is accepted by {}", host.name),
+                    check_token(host, config).await,
+                );
+                healthy &= login.is_some();
+
+                if let Some(login) = login {
+                    let user = &host.user;
+                    if !login.eq_ignore_ascii_case(user) {
+                        println!(
+                            "[warn] token for {} belongs to {login}, not {user}; private repositories of {user} will be missing",
+                            host.name
+                        );
+                    }
+                }
+            }
+        }
+        Err(e) => {
+            report::<String>("access token", Err(e));
+            healthy = false;
+        }
+    }
+
+    for card in config.render.cards() {
+        let template = card.template.display().to_string();
+        let result = if card.template.is_file() {
+            Ok("found".to_string())
+        } else {
+            Err(anyhow!(
+                "missing (run from the repository root or fix the path in the config)"
+            ))
+        };
+        healthy &= report(&template, result).is_some();
+    }
+
+    healthy &= report("cache directory", check_cache_dir(&config.cache.dir)).is_some();
+    let store = Cache::open(&config.cache).map(|cache| format!("{} opened", cache.location()));
+    healthy &= report("cache store", store).is_some();
+
+    healthy
+}
+
+fn report<T: std::fmt::Display>(check: &str, result: Result<T>) -> Option<T> {
+    match result {
+        Ok(detail) => {
+            println!("[ok]   {check}: {detail}");
+            Some(detail)
+        }
+        Err(e) => {
+            println!("[fail] {check}: {e:#}");
+            None
+        }
+    }
+}
+
+async fn check_token(host: &Host, config: &Config) -> Result<String> {
+    let cache = Cache::open(&config.cache)?;
+    let fixtures = config
+        .fixtures
+        .as_ref()
+        .map(|mode| Fixtures::open(mode, host))
+        .transpose()?;
+    let client = GitHubClient::new(host, 1, RetryPolicy::from_config(&config.retry), cache)?
+        .with_fixtures(fixtures);
+    let response = client
+        .graphql_query("query Viewer { viewer { login } }")
+        .await?;
+
+    response.data["viewer"]["login"]
+        .as_str()
+        .map(String::from)
+        .ok_or_else(|| anyhow!("unexpected response: {}", response.data))
+}
+
+fn check_cache_dir(dir: &Path) -> Result<String> {
+    fs::create_dir_all(dir)?;
+    let probe = dir.join(".doctor");
+    fs::write(&probe, b"ok")?;
+    fs::remove_file(&probe)?;
+    Ok(format!("{} is writable", dir.display()))
+}
@@ -0,0 +1,323 @@
+//! Recording of API exchanges to disk, and replaying them without a network.
+//!
+//! Each distinct request is stored as one JSON file in the fixtures
+//! directory, holding every response it received in order. Replaying serves
+//! those responses in the same order, so sequences such as a `202` followed
+//! by a `200` play back as they happened. Files are named after the request
+//! (method, path relative to the API root, and the GraphQL query with its
+//! whitespace collapsed), never after the host or token, so fixtures
+//! recorded against one server replay against any other.
+
+use anyhow::{anyhow, Context, Result};
+use futures::future::{self, BoxFuture};
+use reqwest::{
+    header::{HeaderMap, HeaderName, HeaderValue},
+    StatusCode,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    config::Host,
+    transport::{HttpRequest, HttpResponse, SendError, Transport},
+};
+
+/// Response headers worth keeping; the rest (dates, request IDs, cookies)
+/// only make fixtures noisy.
+const RECORDED_HEADERS: &[&str] = &[
+    "content-type",
+    "etag",
+    "last-modified",
+    "retry-after",
+    "x-ratelimit-limit",
+    "x-ratelimit-remaining",
+    "x-ratelimit-reset",
+    "x-ratelimit-resource",
+];
+
+#[derive(Debug, Clone)]
+pub enum FixtureMode {
+    Record(PathBuf),
+    Replay(PathBuf),
+}
+
+/// The parts of a request that identify it in a fixtures directory.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FixtureRequest {
+    pub method: String,
+    /// Path relative to the API root, e.g. `/repos/octocat/hello/stats/contributors`
+    pub path: String,
+    /// GraphQL query, whitespace collapsed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub query: Option<String>,
+}
+
+impl FixtureRequest {
+    fn from_request(request: &HttpRequest) -> Self {
+        let query = request
+            .body
+            .as_ref()
+            .and_then(|body| body["query"].as_str())
+            .map(|query| query.split_whitespace().collect::<Vec<_>>().join(" "));
+
+        Self {
+            method: request.method.to_string(),
+            path: request.path.clone(),
+            query,
+        }
+    }
+
+    fn file_name(&self) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(self.method.as_bytes());
+        hasher.update(b" ");
+        hasher.update(self.path.as_bytes());
+        if let Some(query) = &self.query {
+            hasher.update(b"\n");
+            hasher.update(query.as_bytes());
+        }
+        let hash = hex::encode(hasher.finalize());
+
+        let slug: String = self
+            .path
+            .trim_start_matches('/')
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .take(60)
+            .collect();
+        format!("{}-{slug}-{}.json", self.method.to_lowercase(), &hash[..12])
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Fixture {
+    request: FixtureRequest,
+    responses: Vec<FixtureResponse>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct FixtureResponse {
+    status: u16,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    headers: BTreeMap<String, String>,
+    /// The body, when it is JSON
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    json: Option<Value>,
+    /// The body, when it is not JSON
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    text: Option<String>,
+}
+
+impl FixtureResponse {
+    fn from_response(response: &HttpResponse) -> Self {
+        let headers = RECORDED_HEADERS
+            .iter()
+            .filter_map(|&name| {
+                let value = response.headers.get(name)?.to_str().ok()?;
+                Some((name.to_string(), value.to_string()))
+            })
+            .collect();
+        let (json, text) = match serde_json::from_slice(&response.body) {
+            Ok(json) => (Some(json), None),
+            Err(_) if response.body.is_empty() => (None, None),
+            Err(_) => (
+                None,
+                Some(String::from_utf8_lossy(&response.body).into_owned()),
+            ),
+        };
+
+        Self {
+            status: response.status.as_u16(),
+            headers,
+            json,
+            text,
+        }
+    }
+
+    fn to_response(&self) -> Result<HttpResponse> {
+        let mut headers = HeaderMap::new();
+        for (name, value) in &self.headers {
+            headers.insert(
+                HeaderName::from_bytes(name.as_bytes())?,
+                HeaderValue::from_str(value)?,
+            );
+        }
+        let body = match (&self.json, &self.text) {
+            (Some(json), _) => serde_json::to_vec(json)?,
+            (None, Some(text)) => text.clone().into_bytes(),
+            (None, None) => Vec::new(),
+        };
+
+        Ok(HttpResponse {
+            status: StatusCode::from_u16(self.status)?,
+            headers,
+            body,
+        })
+    }
+}
+
+/// A fixtures directory being recorded into or replayed from.
+pub struct Fixtures {
+    dir: PathBuf,
+    replay: bool,
+    /// Responses served so far in this run, per fixture file
+    served: Mutex<HashMap<String, usize>>,
+    /// Responses recorded so far in this run, per fixture file
+    recorded: Mutex<HashMap<String, Vec<FixtureResponse>>>,
+}
+
+impl Fixtures {
+    /// Open the fixtures for `host`. The main host uses the directory itself
+    /// and each extra host a subdirectory named after it.
+    pub fn open(mode: &FixtureMode, host: &Host) -> Result<Self> {
+        let (dir, replay) = match mode {
+            FixtureMode::Record(dir) => (dir, false),
+            FixtureMode::Replay(dir) => (dir, true),
+        };
+        let dir = if host.primary {
+            dir.clone()
+        } else {
+            dir.join(&host.name)
+        };
+
+        if replay {
+            if !dir.is_dir() {
+                return Err(anyhow!("fixtures directory {} not found", dir.display()));
+            }
+        } else {
+            fs::create_dir_all(&dir)
+                .with_context(|| format!("Failed to create {}", dir.display()))?;
+        }
+
+        Ok(Self {
+            dir,
+            replay,
+            served: Mutex::new(HashMap::new()),
+            recorded: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Wrap `inner` in a transport that records what it sends, or replace it
+    /// with one that replays.
+    pub fn into_transport(self, inner: Arc<dyn Transport>) -> Arc<dyn Transport> {
+        if self.replay {
+            Arc::new(ReplayTransport { fixtures: self })
+        } else {
+            Arc::new(RecordingTransport {
+                inner,
+                fixtures: self,
+            })
+        }
+    }
+
+    /// Serve the next recorded response for `request`. Once a request's
+    /// responses are used up, the last one is repeated.
+    fn replay(&self, request: &FixtureRequest) -> Result<HttpResponse> {
+        let file_name = request.file_name();
+        let mut served = self.served.lock().expect("fixtures state poisoned");
+
+        let served = served.entry(file_name.clone()).or_default();
+        let path = self.dir.join(&file_name);
+        let contents = fs::read_to_string(&path).with_context(|| {
+            format!(
+                "no fixture for {} {} (expected {})",
+                request.method,
+                request.path,
+                path.display()
+            )
+        })?;
+        let fixture: Fixture = serde_json::from_str(&contents)
+            .with_context(|| format!("Invalid fixture {}", path.display()))?;
+
+        let response = fixture
+            .responses
+            .get(*served)
+            .or_else(|| fixture.responses.last())
+            .ok_or_else(|| anyhow!("fixture {} has no responses", path.display()))?;
+        *served += 1;
+        response.to_response()
+    }
+
+    /// Save a response received for `request`. The first response in a run
+    /// replaces whatever an earlier recording left in the file.
+    fn record(&self, request: &FixtureRequest, response: &HttpResponse) -> Result<()> {
+        let file_name = request.file_name();
+        let mut recorded = self.recorded.lock().expect("fixtures state poisoned");
+
+        let responses = recorded.entry(file_name.clone()).or_default();
+        responses.push(FixtureResponse::from_response(response));
+
+        let fixture = Fixture {
+            request: request.clone(),
+            responses: responses.clone(),
+        };
+        let path = self.dir.join(file_name);
+        fs::write(&path, serde_json::to_string_pretty(&fixture)? + "\n")
+            .with_context(|| format!("Failed to write {}", path.display()))
+    }
+}
+
+/// Passes requests on and saves every response.
+struct RecordingTransport {
+    inner: Arc<dyn Transport>,
+    fixtures: Fixtures,
+}
+
+impl Transport for RecordingTransport {
+    fn send(&self, request: HttpRequest) -> BoxFuture<'_, Result<HttpResponse, SendError>> {
+        Box::pin(async move {
+            let fixture = FixtureRequest::from_request(&request);
+            let response = self.inner.send(request).await?;
+            self.fixtures
+                .record(&fixture, &response)
+                .map_err(SendError::Fatal)?;
+            Ok(response)
+        })
+    }
+
+    fn real_time(&self) -> bool {
+        self.inner.real_time()
+    }
+
+    /// Every request should end up in the fixtures.
+    fn use_cache(&self) -> bool {
+        false
+    }
+}
+
+/// Serves recorded responses without a network.
+struct ReplayTransport {
+    fixtures: Fixtures,
+}
+
+impl Transport for ReplayTransport {
+    fn send(&self, request: HttpRequest) -> BoxFuture<'_, Result<HttpResponse, SendError>> {
+        let fixture = FixtureRequest::from_request(&request);
+        Box::pin(future::ready(
+            self.fixtures.replay(&fixture).map_err(SendError::Fatal),
+        ))
+    }
+
+    /// Replayed responses are already on disk, so there is nothing to wait for.
+    fn real_time(&self) -> bool {
+        false
+    }
+
+    /// Every request should be answered by the fixtures, and nothing
+    /// replayed belongs in the cache.
+    fn use_cache(&self) -> bool {
+        false
+    }
+
+    /// Replayed runs did not happen.
+    fn persist_history(&self) -> bool {
+        false
+    }
+}
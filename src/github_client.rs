@@ -1,125 +1,444 @@
-use anyhow::{anyhow, Result};
-use reqwest::{Client, StatusCode};
+use anyhow::{anyhow, Context, Result};
+use reqwest::{
+    header::{HeaderMap, HeaderValue, AUTHORIZATION},
+    Method, StatusCode,
+};
 use serde_json::{json, Value};
 use std::sync::Arc;
 use tokio::sync::Semaphore;
 use tokio::time::{sleep, Duration};
 
-use crate::cache::Cache;
+use crate::{
+    cache::{Cache, Freshness, Lookup, Stale, Validators},
+    config::Host,
+    fixtures::Fixtures,
+    graphql::{self, GraphQlErrors, GraphQlResponse},
+    rate_limit::{self, RateLimiter, Resource},
+    retry::{self, Attempts, RetryPolicy},
+    transport::{HttpRequest, ReqwestTransport, SendError, Transport},
+};
 
-const MAX_RETRIES: u32 = 10;
+/// How many times one request is retried after hitting a rate limit
+const MAX_RATE_LIMIT_RETRIES: u32 = 5;
+
+/// The outcome of a REST request.
+enum Fetched {
+    Modified(Value, Validators),
+    /// The cached response is still current; carries the headers of the `304`
+    NotModified(HeaderMap),
+}
 
 pub struct GitHubClient {
-    client: Client,
+    transport: Arc<dyn Transport>,
     access_token: String,
+    api_url: String,
+    graphql_url: String,
     semaphore: Arc<Semaphore>,
+    rate_limiter: Arc<RateLimiter>,
+    retry: RetryPolicy,
     cache: Cache,
 }
 
 impl GitHubClient {
-    pub fn new(access_token: String, max_concurrent_requests: usize) -> Self {
-        let client = Client::builder()
-            .user_agent("github-stats-generator")
-            .timeout(Duration::from_secs(30))
-            .build()
-            .expect("Failed to create HTTP client");
-
-        Self {
-            client,
-            access_token,
+    pub fn new(
+        host: &Host,
+        max_concurrent_requests: usize,
+        retry: RetryPolicy,
+        cache: Cache,
+    ) -> Result<Self> {
+        Ok(Self {
+            transport: Arc::new(ReqwestTransport::new()?),
+            access_token: host.token.clone(),
+            api_url: host.api_url.clone(),
+            graphql_url: host.graphql_url.clone(),
             semaphore: Arc::new(Semaphore::new(max_concurrent_requests)),
-            cache: Cache::new(".github_stats_cache", 6),
+            rate_limiter: Arc::new(RateLimiter::default()),
+            retry,
+            cache,
+        })
+    }
+
+    /// Send requests through `transport` instead of the network.
+    pub fn with_transport(mut self, transport: Arc<dyn Transport>) -> Self {
+        self.transport = transport;
+        self
+    }
+
+    /// Record or replay API traffic in a fixtures directory.
+    pub fn with_fixtures(self, fixtures: Option<Fixtures>) -> Self {
+        match fixtures {
+            Some(fixtures) => {
+                let transport = fixtures.into_transport(Arc::clone(&self.transport));
+                self.with_transport(transport)
+            }
+            None => self,
         }
     }
 
-    pub async fn graphql_query(&self, query: &str) -> Result<Value> {
-        let _permit = self.semaphore.acquire().await?;
+    /// A request carrying the token, sent with the given authorization scheme.
+    fn request(&self, method: Method, url: &str, path: &str, scheme: &str) -> HttpRequest {
+        let authorization = format!("{scheme} {}", self.access_token);
+        let mut headers = HeaderMap::new();
+        if let Ok(value) = HeaderValue::from_str(&authorization) {
+            headers.insert(AUTHORIZATION, value);
+        }
 
-        let response = self
-            .client
-            .post("https://api.github.com/graphql")
-            .header("Authorization", format!("Bearer {}", self.access_token))
-            .json(&json!({ "query": query }))
-            .send()
-            .await?;
+        HttpRequest {
+            method,
+            url: url.to_string(),
+            path: path.to_string(),
+            headers,
+            body: None,
+        }
+    }
 
-        let status = response.status();
-        if !status.is_success() {
-            let error_body = response
-                .text()
-                .await
-                .unwrap_or_else(|_| "No error body".to_string());
-            return Err(anyhow!(
-                "GraphQL query failed with status: {}. Body: {}",
-                status,
-                error_body
-            ));
+    /// Run a GraphQL query, answering it from the cache while the cached
+    /// response is fresh by the cache policy for its operation, or by
+    /// `freshness` if given. The cache key covers the whole request body, so
+    /// the query and any variables. Only complete responses are cached;
+    /// partial data is fetched again every time.
+    pub async fn graphql_query_cached(
+        &self,
+        query: &str,
+        freshness: Option<Freshness>,
+    ) -> Result<GraphQlResponse> {
+        if !self.transport.use_cache() {
+            return self.graphql_query(query).await;
+        }
+
+        let operation = graphql::operation_name(query).unwrap_or_default();
+        let mut policy = self.cache.graphql_policy(operation);
+        if let Some(freshness) = freshness {
+            policy.freshness = freshness;
+        }
+        let query_text = query.split_whitespace().collect::<Vec<_>>().join(" ");
+        let cache_key = format!(
+            "graphql:{} {}",
+            self.graphql_url,
+            json!({ "query": query_text })
+        );
+        let stale = match self.cache.lookup(&cache_key, policy) {
+            Lookup::Fresh(data) => {
+                return Ok(GraphQlResponse {
+                    data,
+                    errors: Vec::new(),
+                })
+            }
+            Lookup::Stale(stale) => Some(stale),
+            Lookup::Miss => None,
+        };
+
+        let response = match self.graphql_query(query).await {
+            Ok(response) => response,
+            Err(e) => {
+                let what = format!("GraphQL query {operation}");
+                let data = self.serve_stale(what.trim_end(), e, stale)?;
+                return Ok(GraphQlResponse {
+                    data,
+                    errors: Vec::new(),
+                });
+            }
+        };
+        if response.errors.is_empty() {
+            self.cache
+                .set(&cache_key, &response.data, Validators::default(), policy)?;
         }
+        Ok(response)
+    }
 
-        let data: Value = response.json().await?;
-        Ok(data)
+    /// The data of `stale` in place of a refresh that failed with `error`,
+    /// if its policy lets it stand in; otherwise the error.
+    fn serve_stale(&self, what: &str, error: anyhow::Error, stale: Option<Stale>) -> Result<Value> {
+        match stale {
+            Some(stale) if stale.fallback => {
+                println!(
+                    "  [cache] {what}: {error:#}; serving the response stored {}",
+                    stale.stored.format("%Y-%m-%d %H:%M UTC")
+                );
+                self.cache.record_served_stale();
+                Ok(stale.data)
+            }
+            _ => Err(error),
+        }
     }
 
-    pub async fn rest_get(&self, path: &str) -> Result<Value> {
-        let cache_key = format!("rest:{path}");
+    /// Run a GraphQL query. A response without any data fails with
+    /// [`GraphQlErrors`]; one with partial data is returned together with its
+    /// errors for the caller to report.
+    pub async fn graphql_query(&self, query: &str) -> Result<GraphQlResponse> {
+        let mut attempts = self.retry.start();
+        let mut rate_limit_retries = 0;
+
+        loop {
+            self.rate_limiter.acquire(Resource::GraphQl).await?;
+            let permit = self.semaphore.acquire().await?;
 
-        // Check cache first
-        if let Some(cached) = self.cache.get(&cache_key) {
-            return Ok(cached);
+            let mut request = self.request(Method::POST, &self.graphql_url, "graphql", "Bearer");
+            request.body = Some(json!({ "query": query }));
+
+            let reason = match self.transport.send(request).await {
+                Err(SendError::Transient(e)) => e,
+                Err(SendError::Fatal(e)) => return Err(e),
+                Ok(response) => {
+                    let status = response.status;
+                    let headers = &response.headers;
+                    self.rate_limiter.update(Resource::GraphQl, headers);
+
+                    if status.is_success() {
+                        let data: Value = serde_json::from_slice(&response.body)
+                            .context("GraphQL response is not JSON")?;
+                        self.rate_limiter.update_graphql(&data);
+
+                        if rate_limit::is_graphql_rate_limited(&data) {
+                            drop(permit);
+                            self.wait_for_rate_limit(
+                                Resource::GraphQl,
+                                headers,
+                                &mut rate_limit_retries,
+                            )
+                            .await
+                            .with_context(|| {
+                                format!("GraphQL query was rate limited: {}", data["errors"])
+                            })?;
+                            continue;
+                        }
+                        if !retry::is_retryable_graphql(&data) {
+                            let response: GraphQlResponse = serde_json::from_value(data)
+                                .context("GraphQL response is not a JSON object")?;
+                            if response.data.is_null() {
+                                return Err(GraphQlErrors(response.errors).into());
+                            }
+                            return Ok(response);
+                        }
+                        format!("errors: {}", data["errors"])
+                    } else {
+                        let error_body = response.text();
+
+                        if rate_limit::is_rate_limited(status, headers, &error_body) {
+                            drop(permit);
+                            self.wait_for_rate_limit(
+                                Resource::GraphQl,
+                                headers,
+                                &mut rate_limit_retries,
+                            )
+                            .await
+                            .with_context(|| {
+                                format!("GraphQL query was rate limited with status: {status}. Body: {error_body}")
+                            })?;
+                            continue;
+                        }
+                        if !retry::is_retryable_status(status) {
+                            return Err(anyhow!(
+                                "GraphQL query failed with status: {}. Body: {}",
+                                status,
+                                error_body
+                            ));
+                        }
+                        format!("status: {status}")
+                    }
+                }
+            };
+
+            drop(permit);
+            self.wait_to_retry(&mut attempts, "GraphQL query", &reason)
+                .await?;
         }
+    }
 
+    pub async fn rest_get(&self, path: &str) -> Result<Value> {
         let url = if path.starts_with('/') {
-            format!("https://api.github.com{path}")
+            format!("{}{path}", self.api_url)
         } else {
-            format!("https://api.github.com/{path}")
+            format!("{}/{path}", self.api_url)
+        };
+        if !self.transport.use_cache() {
+            return match self.fetch_rest(path, &url, &Validators::default()).await? {
+                Fetched::Modified(data, _) => Ok(data),
+                Fetched::NotModified(_) => Err(anyhow!("{path} was not modified")),
+            };
+        }
+
+        let cache_key = format!("rest:{url}");
+        let policy = self.cache.rest_policy(path);
+
+        // Check cache first. An expired entry is sent back to GitHub for
+        // revalidation; a `304` costs no quota.
+        let stale = match self.cache.lookup(&cache_key, policy) {
+            Lookup::Fresh(cached) => return Ok(cached),
+            Lookup::Stale(stale) => Some(stale),
+            Lookup::Miss => None,
         };
+        let validators = stale
+            .as_ref()
+            .map(|stale| stale.validators.clone())
+            .unwrap_or_default();
+
+        match self.fetch_rest(path, &url, &validators).await {
+            Ok(Fetched::Modified(data, validators)) => {
+                self.cache.set(&cache_key, &data, validators, policy)?;
+                Ok(data)
+            }
+            Ok(Fetched::NotModified(headers)) => {
+                let stale = stale.ok_or_else(|| anyhow!("{path} was not modified"))?;
+                self.cache.record_revalidated();
+                let validators = stale.validators.updated(&headers);
+                self.cache
+                    .set(&cache_key, &stale.data, validators, policy)?;
+                Ok(stale.data)
+            }
+            Err(e) => self.serve_stale(path, e, stale),
+        }
+    }
 
-        let mut retries = 0;
+    /// GET `url`, conditionally if there are `validators`. Statistics still
+    /// being computed (`202`) are waited for, never returned.
+    async fn fetch_rest(&self, path: &str, url: &str, validators: &Validators) -> Result<Fetched> {
+        let mut attempts = self.retry.start();
+        let mut rate_limit_retries = 0;
 
         loop {
+            self.rate_limiter.acquire(Resource::Core).await?;
             let permit = self.semaphore.acquire().await?;
 
-            let response = self
-                .client
-                .get(&url)
-                .header("Authorization", format!("token {}", self.access_token))
-                .send()
-                .await?;
+            let mut request = self.request(Method::GET, url, path, "token");
+            validators.apply(&mut request.headers);
+
+            let response = match self.transport.send(request).await {
+                Ok(response) => response,
+                Err(SendError::Transient(e)) => {
+                    drop(permit);
+                    self.wait_to_retry(&mut attempts, path, &e).await?;
+                    continue;
+                }
+                Err(SendError::Fatal(e)) => return Err(e),
+            };
+            self.rate_limiter.update(Resource::Core, &response.headers);
+
+            if response.status == StatusCode::NOT_MODIFIED && !validators.is_empty() {
+                return Ok(Fetched::NotModified(response.headers));
+            }
 
-            match response.status() {
+            match response.status {
                 StatusCode::OK => {
-                    let data: Value = response.json().await?;
-                    // Cache successful response
-                    self.cache.set(&cache_key, &data)?;
-                    return Ok(data);
+                    let data: Value = serde_json::from_slice(&response.body)
+                        .with_context(|| format!("{path} did not return JSON"))?;
+                    let validators = Validators::from_headers(&response.headers);
+                    return Ok(Fetched::Modified(data, validators));
                 }
                 StatusCode::ACCEPTED => {
                     // 202 means data is being calculated, retry
-                    retries += 1;
-                    if retries >= MAX_RETRIES {
-                        return Err(anyhow!("Too many retries for {}", path));
-                    }
-                    if retries == 5 || retries == MAX_RETRIES {
+                    drop(permit); // Release semaphore before sleeping
+                    let delay = attempts
+                        .next_delay()
+                        .ok_or_else(|| anyhow!("Too many retries for {}", path))?;
+                    if attempts.number() == attempts.max() / 2 + 1 {
                         println!(
                             "Still waiting for {} statistics (attempt {}/{})",
                             path.split('/').nth(2).unwrap_or("repo"),
-                            retries,
-                            MAX_RETRIES
+                            attempts.number(),
+                            attempts.max()
                         );
                     }
-                    drop(permit); // Release semaphore before sleeping
-                    sleep(Duration::from_secs(1)).await;
+                    self.pause(delay).await;
                 }
-                _ => {
-                    return Err(anyhow!(
-                        "REST API request failed with status: {}",
-                        response.status()
-                    ));
+                status => {
+                    let body = response.text();
+                    drop(permit);
+
+                    if rate_limit::is_rate_limited(status, &response.headers, &body) {
+                        self.wait_for_rate_limit(
+                            Resource::Core,
+                            &response.headers,
+                            &mut rate_limit_retries,
+                        )
+                        .await
+                        .with_context(|| {
+                            format!("{path} was rate limited with status: {status}")
+                        })?;
+                    } else if retry::is_retryable_status(status) {
+                        self.wait_to_retry(&mut attempts, path, &format!("status: {status}"))
+                            .await?;
+                    } else {
+                        return Err(anyhow!("REST API request failed with status: {}", status));
+                    }
                 }
             }
         }
     }
 
+    /// Sleep between attempts, unless the transport serves canned responses
+    /// that will not change by waiting.
+    async fn pause(&self, delay: Duration) {
+        if self.transport.real_time() {
+            sleep(delay).await;
+        }
+    }
+
+    /// Sleep before retrying a transient failure, or fail once the retry
+    /// policy is used up.
+    async fn wait_to_retry(
+        &self,
+        attempts: &mut Attempts,
+        request: &str,
+        reason: &str,
+    ) -> Result<()> {
+        let Some(delay) = attempts.next_delay() else {
+            return Err(anyhow!(
+                "{request} failed after {} attempts ({reason})",
+                attempts.number()
+            ));
+        };
+        println!(
+            "{request} failed ({reason}); retrying in {:.1}s (attempt {}/{})",
+            delay.as_secs_f64(),
+            attempts.number(),
+            attempts.max()
+        );
+        self.pause(delay).await;
+        Ok(())
+    }
+
+    /// Back off after a rate-limited response, or fail once retrying is
+    /// hopeless.
+    async fn wait_for_rate_limit(
+        &self,
+        resource: Resource,
+        headers: &HeaderMap,
+        retries: &mut u32,
+    ) -> Result<()> {
+        *retries += 1;
+        if *retries > MAX_RATE_LIMIT_RETRIES {
+            return Err(anyhow!("gave up after {MAX_RATE_LIMIT_RETRIES} retries"));
+        }
+        let delay = self
+            .rate_limiter
+            .backoff(resource, headers)
+            .ok_or_else(|| anyhow!("{resource} quota does not reset soon enough to wait"))?;
+        println!(
+            "{resource} rate limit hit; backing off for {}s (attempt {}/{MAX_RATE_LIMIT_RETRIES})",
+            delay.as_secs(),
+            retries
+        );
+        self.pause(delay).await;
+        Ok(())
+    }
+
+    /// Cache hits and misses so far, if the cache was used.
+    pub fn cache_summary(&self) -> Option<String> {
+        self.cache.summary()
+    }
+
+    /// Remaining API quota, one line per rate limit seen so far.
+    pub fn rate_limit_summary(&self) -> Vec<String> {
+        self.rate_limiter.summary()
+    }
+
+    /// Whether the responses are real, so that what is collected from them
+    /// may be recorded.
+    pub fn persist_history(&self) -> bool {
+        self.transport.persist_history()
+    }
+
     pub async fn rest_get_batch(&self, paths: Vec<String>) -> Vec<(String, Result<Value>)> {
         let mut handles = vec![];
 
@@ -146,9 +465,13 @@ impl GitHubClient {
 impl Clone for GitHubClient {
     fn clone(&self) -> Self {
         Self {
-            client: self.client.clone(),
+            transport: Arc::clone(&self.transport),
             access_token: self.access_token.clone(),
+            api_url: self.api_url.clone(),
+            graphql_url: self.graphql_url.clone(),
             semaphore: Arc::clone(&self.semaphore),
+            rate_limiter: Arc::clone(&self.rate_limiter),
+            retry: self.retry,
             cache: self.cache.clone(),
         }
     }
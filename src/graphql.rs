@@ -0,0 +1,105 @@
+use serde::Deserialize;
+use serde_json::Value;
+use std::fmt;
+
+/// A GraphQL response body. GitHub may return `data` and `errors` together:
+/// when part of a query fails (an organization enforcing SAML single sign-on,
+/// a repository the token cannot read) the affected fields come back `null`
+/// and each failure is listed in `errors`.
+#[derive(Debug, Deserialize)]
+pub struct GraphQlResponse {
+    #[serde(default)]
+    pub data: Value,
+    #[serde(default)]
+    pub errors: Vec<GraphQlError>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct GraphQlError {
+    pub message: String,
+    /// Machine-readable category such as `FORBIDDEN` or `NOT_FOUND`
+    #[serde(rename = "type")]
+    pub kind: Option<String>,
+    /// Where in the response the failed field would have been
+    #[serde(default)]
+    pub path: Vec<PathSegment>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum PathSegment {
+    Field(String),
+    Index(usize),
+}
+
+impl GraphQlError {
+    /// The response path in the usual dotted form, e.g.
+    /// `viewer.repositoriesContributedTo.nodes[3]`.
+    pub fn path(&self) -> String {
+        let mut path = String::new();
+        for segment in &self.path {
+            match segment {
+                PathSegment::Field(field) => {
+                    if !path.is_empty() {
+                        path.push('.');
+                    }
+                    path.push_str(field);
+                }
+                PathSegment::Index(index) => {
+                    path.push('[');
+                    path.push_str(&index.to_string());
+                    path.push(']');
+                }
+            }
+        }
+        path
+    }
+}
+
+impl fmt::Display for GraphQlError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(kind) = &self.kind {
+            write!(f, "{kind}: ")?;
+        }
+        f.write_str(&self.message)?;
+        if !self.path.is_empty() {
+            write!(f, " (at {})", self.path())?;
+        }
+        Ok(())
+    }
+}
+
+/// Every error of a GraphQL response, as a single error value.
+#[derive(Debug)]
+pub struct GraphQlErrors(pub Vec<GraphQlError>);
+
+impl fmt::Display for GraphQlErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0.as_slice() {
+            [] => f.write_str("GraphQL response contained neither data nor errors"),
+            [error] => write!(f, "GraphQL error: {error}"),
+            errors => {
+                write!(f, "{} GraphQL errors:", errors.len())?;
+                for error in errors {
+                    write!(f, "\n  - {error}")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for GraphQlErrors {}
+
+/// The name of the operation in `query`, e.g. `Contributions` for
+/// `query Contributions { ... }`; `None` for anonymous queries.
+pub fn operation_name(query: &str) -> Option<&str> {
+    let rest = query
+        .trim_start()
+        .strip_prefix("query")?
+        .trim_start_matches(char::is_whitespace);
+    let end = rest
+        .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+        .unwrap_or(rest.len());
+    Some(&rest[..end]).filter(|name| !name.is_empty())
+}
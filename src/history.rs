@@ -0,0 +1,132 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Duration, Utc};
+use std::{
+    fs::{self, OpenOptions},
+    io::Write,
+    path::Path,
+};
+
+use crate::{snapshot::Snapshot, stats::Stats};
+
+/// Extracts one number from a run's statistics, such as the star count.
+pub type Metric = fn(&Stats) -> u64;
+
+/// Every snapshot ever collected, oldest first.
+///
+/// The store is an append-only JSONL file with one [`Snapshot`] per line, so a
+/// run that dies halfway through can at worst leave one truncated line behind.
+/// Such lines are skipped with a warning instead of discarding the history.
+#[derive(Default)]
+pub struct History {
+    records: Vec<Snapshot>,
+}
+
+impl History {
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read history {}", path.display()))?;
+
+        let mut records = Vec::new();
+        for (line_number, line) in contents.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            match serde_json::from_str::<Snapshot>(line) {
+                Ok(record) => records.push(record),
+                Err(e) => println!(
+                    "  [history] skipping line {} of {}: {e}",
+                    line_number + 1,
+                    path.display()
+                ),
+            }
+        }
+        records.sort_by_key(|r| r.generated_at);
+
+        Ok(Self { records })
+    }
+
+    pub fn append(path: &Path, snapshot: &Snapshot) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        // Per-day calendars are large and already in every snapshot, so the
+        // history only keeps the totals
+        let mut record = serde_json::to_value(snapshot)?;
+        if let Some(stats) = record["stats"].as_object_mut() {
+            stats.remove("contribution_calendar");
+        }
+
+        let mut line = serde_json::to_string(&record)?;
+        line.push('\n');
+
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .and_then(|mut file| file.write_all(line.as_bytes()))
+            .with_context(|| format!("Failed to append to history {}", path.display()))
+    }
+
+    pub fn records(&self) -> &[Snapshot] {
+        &self.records
+    }
+
+    pub fn latest(&self) -> Option<&Snapshot> {
+        self.records.last()
+    }
+
+    /// Records collected within `window` of the latest record, oldest first.
+    pub fn window(&self, window: Duration) -> &[Snapshot] {
+        let Some(latest) = self.latest() else {
+            return &[];
+        };
+        let start = latest.generated_at - window;
+        let first = self.records.partition_point(|r| r.generated_at < start);
+        &self.records[first..]
+    }
+
+    /// How much `metric` changed over `window`, measured back from the latest
+    /// record.
+    ///
+    /// The baseline is the newest record at least `window` old, or the oldest
+    /// record when the history does not reach back that far yet. Returns `None`
+    /// until there are at least two records to compare.
+    pub fn change_over(&self, window: Duration, metric: Metric) -> Option<i64> {
+        let latest = self.latest()?;
+        let cutoff = latest.generated_at - window;
+        let baseline_index = self
+            .records
+            .partition_point(|r| r.generated_at <= cutoff)
+            .saturating_sub(1);
+        let baseline = &self.records[baseline_index];
+
+        if std::ptr::eq(baseline, latest) {
+            return None;
+        }
+
+        let now = i64::try_from(metric(&latest.stats)).unwrap_or(i64::MAX);
+        let then = i64::try_from(metric(&baseline.stats)).unwrap_or(i64::MAX);
+        Some(now - then)
+    }
+
+    /// Percentage of code in `language` at each recorded run, oldest first.
+    pub fn language_share(&self, language: &str) -> Vec<(DateTime<Utc>, f64)> {
+        self.records
+            .iter()
+            .map(|r| {
+                let share = r
+                    .stats
+                    .languages
+                    .iter()
+                    .find(|(name, _)| name.eq_ignore_ascii_case(language))
+                    .map_or(0.0, |(_, info)| info.percentage);
+                (r.generated_at, share)
+            })
+            .collect()
+    }
+}
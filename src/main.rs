@@ -1,38 +1,83 @@
 #![warn(clippy::pedantic)]
 
-use anyhow::Result;
-use std::env;
+use anyhow::{Context, Result};
+use chrono::{Duration, Utc};
+use clap::Parser;
+use std::{fs, io::ErrorKind, process::ExitCode};
 
 mod cache;
+mod cache_store;
+mod cli;
+mod config;
+mod doctor;
+mod fixtures;
 mod github_client;
+mod graphql;
+mod history;
+mod models;
+mod rate_limit;
+mod retry;
+mod snapshot;
 mod stats;
 mod svg_generator;
+mod traffic;
+mod transport;
 
-use crate::{stats::StatsCollector, svg_generator::SvgGenerator};
+use crate::{
+    cache::{Cache, EntryInfo},
+    cli::{CacheAction, Cli, CollectArgs, Command, HistoryArgs},
+    config::Config,
+    history::{History, Metric},
+    snapshot::Snapshot,
+    stats::{Stats, StatsCollector},
+    svg_generator::SvgGenerator,
+    traffic::TrafficHistory,
+};
 
 #[tokio::main]
-async fn main() -> Result<()> {
-    // Get environment variables
-    let access_token = env::var("ACCESS_TOKEN")
-        .or_else(|_| env::var("GITHUB_TOKEN"))
-        .expect("ACCESS_TOKEN or GITHUB_TOKEN environment variable is required");
+async fn main() -> ExitCode {
+    let cli = Cli::parse();
 
-    let username = env::var("GITHUB_ACTOR").expect("GITHUB_ACTOR environment variable is required");
+    let mut config = match Config::load(cli.config.as_deref()) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Error: {e:#}");
+            return ExitCode::from(2);
+        }
+    };
+    config.apply_global_args(&cli);
 
-    let excluded_repos: Vec<String> = env::var("EXCLUDED")
-        .ok()
-        .map(|s| s.split(',').map(|r| r.trim().to_string()).collect())
-        .unwrap_or_default();
+    let result = match &cli.command {
+        Command::Collect(args) => collect(&mut config, args).await,
+        Command::Render => render(&config),
+        Command::History(args) => history(&config, args),
+        Command::Cache { action } => cache(&config, action),
+        Command::Doctor(args) => {
+            config.apply_github_args(args);
+            if doctor::run(args, &config).await {
+                Ok(())
+            } else {
+                return ExitCode::FAILURE;
+            }
+        }
+    };
 
-    let excluded_langs: Vec<String> = env::var("EXCLUDED_LANGS")
-        .ok()
-        .map(|s| s.split(',').map(|l| l.trim().to_string()).collect())
-        .unwrap_or_default();
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {e:#}");
+            ExitCode::FAILURE
+        }
+    }
+}
 
-    let exclude_forked = env::var("EXCLUDE_FORKED_REPOS")
-        .ok()
-        .is_some_and(|s| s.trim().to_lowercase() != "false");
+async fn collect(config: &mut Config, args: &CollectArgs) -> Result<()> {
+    config.apply_collect_args(args)?;
+    let hosts = config.hosts(args.github.token()?)?;
+    let username = config.user()?;
 
+    let excluded_repos = &config.collect.exclude_repos;
+    let excluded_langs = &config.collect.exclude_langs;
     if !excluded_repos.is_empty() {
         println!("Excluding repos: {excluded_repos:?}");
     }
@@ -41,28 +86,243 @@ async fn main() -> Result<()> {
     } else {
         println!("Excluding languages: {excluded_langs:?} (plus HTML by default)");
     }
-    if exclude_forked {
+    if config.collect.exclude_forked {
         println!("Excluding forked repositories");
     }
 
-    // Collect statistics
-    println!("Collecting GitHub statistics for {username}...");
-    let stats_collector = StatsCollector::new(
-        &username,
-        access_token,
-        excluded_repos,
-        &excluded_langs,
-        exclude_forked,
+    // Collect statistics, adding up every configured host
+    let mut stats = Stats::default();
+    let mut persist = true;
+    for host in &hosts {
+        println!(
+            "Collecting GitHub statistics for {} on {}...",
+            host.user, host.name
+        );
+        let stats_collector = StatsCollector::new(host, config)?;
+        let host_stats = stats_collector
+            .collect_all_stats()
+            .await
+            .with_context(|| format!("collecting from {}", host.name))?;
+        for line in stats_collector.rate_limit_summary() {
+            println!("Rate limit on {}: {line}", host.name);
+        }
+        if let Some(summary) = stats_collector.cache_summary() {
+            println!("Cache on {}: {summary}", host.name);
+        }
+        persist &= stats_collector.persist_history();
+        stats.merge(host_stats);
+    }
+    stats.username = username.to_string();
+
+    // Replayed runs are not recorded
+    if config.traffic.enabled && persist {
+        let path = &config.traffic.path;
+        let mut traffic = TrafficHistory::load(path)?;
+        let added = traffic.merge(&stats.traffic, Utc::now().date_naive());
+        traffic.save(path)?;
+        println!("Recorded {added} new days of traffic in {}", path.display());
+        stats.view_history = traffic.view_stats();
+        stats.clone_history = traffic.clone_stats();
+    }
+    print_summary(&stats);
+
+    let snapshot = Snapshot::new(stats);
+    snapshot.write(&config.snapshot)?;
+    println!("Wrote snapshot to {}", config.snapshot.display());
+
+    if config.history.enabled && persist {
+        History::append(&config.history.path, &snapshot)?;
+        println!("Recorded run in {}", config.history.path.display());
+    }
+    Ok(())
+}
+
+fn render(config: &Config) -> Result<()> {
+    let snapshot = Snapshot::read(&config.snapshot)?;
+    println!(
+        "Rendering statistics collected at {} from {}...",
+        snapshot.generated_at.format("%Y-%m-%d %H:%M UTC"),
+        config.snapshot.display()
     );
 
-    let stats = stats_collector.collect_all_stats().await?;
+    let history = if config.history.enabled {
+        History::load(&config.history.path)?
+    } else {
+        History::default()
+    };
 
     // Generate SVGs
-    println!("Generating SVG files...");
-
-    SvgGenerator::generate_overview(&stats)?;
-    SvgGenerator::generate_languages(&stats)?;
+    SvgGenerator::generate_overview(&snapshot.stats, &history, &config.render)?;
+    SvgGenerator::generate_languages(&snapshot.stats, &config.render)?;
+    SvgGenerator::generate_trends(&snapshot.stats, &history, &config.render)?;
+    SvgGenerator::generate_calendar(&snapshot.stats, &config.render)?;
+    SvgGenerator::generate_streaks(&snapshot.stats, &config.render)?;
+    SvgGenerator::generate_traffic(&snapshot.stats, &config.render)?;
 
     println!("Successfully generated statistics!");
     Ok(())
 }
+
+fn history(config: &Config, args: &HistoryArgs) -> Result<()> {
+    let history = History::load(&config.history.path)?;
+    let records = history.records();
+    let (Some(first), Some(latest)) = (records.first(), records.last()) else {
+        println!("No runs recorded in {} yet", config.history.path.display());
+        return Ok(());
+    };
+
+    println!(
+        "{} runs recorded between {} and {}",
+        records.len(),
+        first.generated_at.format("%Y-%m-%d"),
+        latest.generated_at.format("%Y-%m-%d")
+    );
+
+    let window = Duration::days(i64::from(args.days));
+    let metrics: [(&str, Metric); 5] = [
+        ("Stars", |s| s.total_stars),
+        ("Forks", |s| s.total_forks),
+        ("Contributions", |s| s.total_contributions),
+        ("Lines changed", |s| s.lines_added + s.lines_deleted),
+        ("Repositories", |s| s.total_repos as u64),
+    ];
+    println!("Change over the last {} days:", args.days);
+    for (label, metric) in metrics {
+        match history.change_over(window, metric) {
+            Some(change) => println!("  {label:<14} {change:+}"),
+            None => println!("  {label:<14} n/a"),
+        }
+    }
+
+    if let Some(language) = &args.language {
+        println!("{language} share of code:");
+        for (timestamp, share) in history.language_share(language) {
+            println!("  {}  {share:.2}%", timestamp.format("%Y-%m-%d %H:%M"));
+        }
+    }
+
+    Ok(())
+}
+
+fn cache(config: &Config, action: &CacheAction) -> Result<()> {
+    let dir = config.cache.dir.display();
+    match action {
+        CacheAction::Ls => {
+            let cache = Cache::open(&config.cache)?;
+            let location = cache.location();
+            let entries = cache.entries()?;
+            if entries.is_empty() {
+                println!("{location} is empty");
+                return Ok(());
+            }
+
+            let now = Utc::now();
+            println!("{:>6}  {:>8}  {:<12}  KEY", "AGE", "SIZE", "STATUS");
+            for entry in &entries {
+                let age = entry
+                    .stored_at
+                    .map_or_else(|| "-".to_string(), |stored| format_age(now - stored));
+                let key = entry
+                    .key
+                    .clone()
+                    .unwrap_or_else(|| format!("({})", entry.id()));
+                println!(
+                    "{age:>6}  {:>8}  {:<12}  {key}",
+                    format_size(entry.size()),
+                    entry.status.as_str()
+                );
+            }
+            let total = entries.iter().map(EntryInfo::size).sum();
+            println!(
+                "{} files, {} in {location}",
+                entries.len(),
+                format_size(total)
+            );
+        }
+        CacheAction::Prune { older_than } => {
+            let older_than = older_than.map(|days| Duration::days(i64::from(days)));
+            let cache = Cache::open(&config.cache)?;
+            let removed = cache.prune(older_than)?;
+            println!(
+                "Removed {} files ({}) from {}",
+                removed.files,
+                format_size(removed.bytes),
+                cache.location()
+            );
+        }
+        CacheAction::Clear {
+            pattern: Some(pattern),
+        } => {
+            let removed = Cache::open(&config.cache)?.clear_matching(pattern)?;
+            println!(
+                "Removed {} responses matching {pattern} ({})",
+                removed.files,
+                format_size(removed.bytes)
+            );
+        }
+        CacheAction::Clear { pattern: None } => match fs::remove_dir_all(&config.cache.dir) {
+            Ok(()) => println!("Removed {dir}"),
+            Err(e) if e.kind() == ErrorKind::NotFound => println!("{dir} does not exist"),
+            Err(e) => return Err(e.into()),
+        },
+    }
+    Ok(())
+}
+
+fn format_age(age: Duration) -> String {
+    if age.num_days() > 0 {
+        format!("{}d", age.num_days())
+    } else if age.num_hours() > 0 {
+        format!("{}h", age.num_hours())
+    } else if age.num_minutes() > 0 {
+        format!("{}m", age.num_minutes())
+    } else {
+        format!("{}s", age.num_seconds().max(0))
+    }
+}
+
+fn format_size(bytes: u64) -> String {
+    #[allow(clippy::cast_precision_loss)]
+    let size = bytes as f64;
+    if bytes >= 1 << 20 {
+        format!("{:.1} MiB", size / f64::from(1 << 20))
+    } else if bytes >= 1 << 10 {
+        format!("{:.1} KiB", size / f64::from(1 << 10))
+    } else {
+        format!("{bytes} B")
+    }
+}
+
+fn print_summary(stats: &Stats) {
+    println!("Name:          {}", stats.name);
+    println!("Stars:         {}", stats.total_stars);
+    println!("Forks:         {}", stats.total_forks);
+    println!("Contributions: {}", stats.total_contributions);
+    println!("Pull requests: {}", stats.total_pull_requests);
+    println!("Issues:        {}", stats.total_issues);
+    println!("Reviews:       {}", stats.total_reviews);
+    println!(
+        "Merged PRs:    {} own, {} external in {} repos ({:.0}% merge rate)",
+        stats.pull_requests.owned.merged,
+        stats.pull_requests.external.merged,
+        stats.pull_requests.external_repos_merged,
+        stats.pull_requests.merge_rate * 100.0
+    );
+    println!("Lines added:   {}", stats.lines_added);
+    println!("Lines deleted: {}", stats.lines_deleted);
+    println!(
+        "Views:         {} in the last 14 days ({} unique)",
+        stats.total_views, stats.unique_views
+    );
+    if let Some(since) = stats.view_history.since {
+        println!(
+            "               {} since {since} ({} unique)",
+            stats.view_history.total, stats.view_history.uniques
+        );
+    }
+    println!("Clones:        {} in the last 14 days", stats.total_clones);
+    println!("Repositories:  {}", stats.total_repos);
+    println!("Languages:     {}", stats.languages.len());
+    println!("Current streak: {} days", stats.streaks.current);
+    println!("Longest streak: {} days", stats.streaks.longest);
+}
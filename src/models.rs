@@ -0,0 +1,305 @@
+//! Shapes of the GitHub API payloads the collector reads.
+//!
+//! Only the fields that are queried are modelled. Fields that GitHub may
+//! return as `null` are `Option`s; everything else is required, so a change
+//! in the schema fails with the path of the offending field instead of
+//! quietly counting as zero.
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+/// Deserialize an API payload, reporting the path of the field that did not
+/// match, e.g. `viewer.repositories.nodes[3].forkCount`.
+pub fn decode<T: DeserializeOwned>(value: Value) -> Result<T> {
+    serde_path_to_error::deserialize(value).map_err(|e| {
+        let target = std::any::type_name::<T>()
+            .rsplit("::")
+            .next()
+            .unwrap_or_default();
+        anyhow!(
+            "unexpected {target} payload at `{}`: {}",
+            e.path(),
+            e.inner()
+        )
+    })
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Connection<T> {
+    pub page_info: PageInfo,
+    /// Nodes the token may not see (e.g. behind SAML single sign-on) are `null`
+    pub nodes: Vec<Option<T>>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PageInfo {
+    pub has_next_page: bool,
+    pub end_cursor: Option<String>,
+}
+
+impl PageInfo {
+    /// Cursor for the next page, or `None` on the last page.
+    pub fn next(self) -> Option<String> {
+        if self.has_next_page {
+            self.end_cursor
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TotalCount {
+    pub total_count: u64,
+}
+
+// Repositories
+
+#[derive(Debug, Deserialize)]
+pub struct ReposData {
+    pub viewer: ReposViewer,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReposViewer {
+    pub login: String,
+    pub name: Option<String>,
+    pub repositories: Connection<Repository>,
+    pub repositories_contributed_to: Connection<Repository>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Repository {
+    pub name_with_owner: String,
+    pub stargazers: TotalCount,
+    pub fork_count: u64,
+    pub languages: LanguageConnection,
+    /// `null` when authenticated as a GitHub App
+    pub viewer_permission: Option<RepositoryPermission>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum RepositoryPermission {
+    Admin,
+    Maintain,
+    Write,
+    Triage,
+    Read,
+}
+
+impl RepositoryPermission {
+    /// Whether the permission includes push access, which reading traffic needs.
+    pub fn can_push(self) -> bool {
+        matches!(self, Self::Admin | Self::Maintain | Self::Write)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LanguageConnection {
+    pub edges: Vec<LanguageEdge>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LanguageEdge {
+    /// Bytes of code in this language
+    pub size: u64,
+    pub node: Language,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Language {
+    pub name: String,
+    pub color: Option<String>,
+}
+
+// Contributions
+
+#[derive(Debug, Deserialize)]
+pub struct ContributionYearsData {
+    pub viewer: ContributionYearsViewer,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ContributionYearsViewer {
+    pub contributions_collection: ContributionYears,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ContributionYears {
+    pub contribution_years: Vec<i32>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ContributionsData {
+    /// One aliased collection per year, keyed `year2021`, `year2022`, ...
+    pub viewer: BTreeMap<String, ContributionsCollection>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ContributionsCollection {
+    pub total_pull_request_contributions: u64,
+    pub total_issue_contributions: u64,
+    pub total_pull_request_review_contributions: u64,
+    pub total_repository_contributions: u64,
+    pub contribution_calendar: ContributionCalendar,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ContributionCalendar {
+    pub total_contributions: u64,
+    pub weeks: Vec<ContributionWeek>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ContributionWeek {
+    pub contribution_days: Vec<CalendarDay>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CalendarDay {
+    pub date: NaiveDate,
+    pub contribution_count: u32,
+    pub color: String,
+}
+
+// Pull requests
+
+#[derive(Debug, Deserialize)]
+pub struct PullRequestsData {
+    pub viewer: PullRequestsViewer,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PullRequestsViewer {
+    pub pull_requests: Connection<PullRequest>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PullRequest {
+    pub state: PullRequestState,
+    pub repository: RepositoryName,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum PullRequestState {
+    Open,
+    Closed,
+    Merged,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RepositoryName {
+    pub name_with_owner: String,
+}
+
+// REST: /repos/{repo}/stats/contributors
+
+#[derive(Debug, Deserialize)]
+pub struct ContributorStats {
+    /// `null` for commits by accounts that no longer exist
+    pub author: Option<Author>,
+    pub weeks: Vec<ContributorWeek>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Author {
+    pub login: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ContributorWeek {
+    /// Lines added
+    pub a: u64,
+    /// Lines deleted
+    pub d: u64,
+}
+
+impl ContributorStats {
+    pub fn is(&self, username: &str) -> bool {
+        self.author
+            .as_ref()
+            .is_some_and(|a| a.login.eq_ignore_ascii_case(username))
+    }
+
+    pub fn lines_added(&self) -> u64 {
+        self.weeks.iter().map(|w| w.a).sum()
+    }
+
+    pub fn lines_deleted(&self) -> u64 {
+        self.weeks.iter().map(|w| w.d).sum()
+    }
+}
+
+// REST: /repos/{repo}/traffic/views
+
+#[derive(Debug, Deserialize)]
+pub struct TrafficViews {
+    /// Views over the last 14 days
+    pub count: u64,
+    /// Distinct visitors over the last 14 days
+    pub uniques: u64,
+    /// The same views, one entry per day
+    pub views: Vec<TrafficDay>,
+}
+
+// REST: /repos/{repo}/traffic/clones
+
+#[derive(Debug, Deserialize)]
+pub struct TrafficClones {
+    /// Clones over the last 14 days
+    pub count: u64,
+    /// Distinct cloners over the last 14 days
+    pub uniques: u64,
+    /// The same clones, one entry per day
+    pub clones: Vec<TrafficDay>,
+}
+
+/// A day of views or clones.
+#[derive(Debug, Clone, Deserialize)]
+pub struct TrafficDay {
+    /// Midnight UTC of the day
+    pub timestamp: DateTime<Utc>,
+    pub count: u64,
+    pub uniques: u64,
+}
+
+// REST: /repos/{repo}/traffic/popular/referrers, a list of these
+
+/// A site that sent visitors over the last 14 days.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Referrer {
+    #[serde(rename = "referrer")]
+    pub name: String,
+    pub count: u64,
+    pub uniques: u64,
+}
+
+// REST: /repos/{repo}/traffic/popular/paths, a list of these
+
+/// A page of the repository that was viewed over the last 14 days.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PopularPath {
+    /// Starts with the repository, e.g. `/octo/alpha/blob/main/README.md`
+    pub path: String,
+    pub title: String,
+    pub count: u64,
+    pub uniques: u64,
+}
@@ -0,0 +1,253 @@
+use anyhow::{bail, Result};
+use chrono::{DateTime, Local, TimeZone, Utc};
+use reqwest::{header::HeaderMap, StatusCode};
+use serde_json::Value;
+use std::{fmt, sync::Mutex};
+use tokio::time::{sleep, Duration, Instant};
+
+/// Below this many remaining requests, calls are spread out over the time
+/// left until the quota resets instead of being sent as fast as possible.
+const THROTTLE_BELOW: u64 = 100;
+
+/// GitHub asks clients to wait at least a minute after a secondary rate
+/// limit response that carries no `Retry-After` header.
+const SECONDARY_LIMIT_BACKOFF: Duration = Duration::from_secs(MINUTE);
+
+/// Longest pause accepted before giving up, so a run cannot hang for an hour.
+const MAX_PAUSE: Duration = Duration::from_secs(15 * MINUTE);
+
+/// Seconds in a minute; `Duration::from_mins` needs a newer toolchain.
+const MINUTE: u64 = 60;
+
+/// The two quotas GitHub tracks separately for a token.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resource {
+    Core,
+    GraphQl,
+}
+
+impl fmt::Display for Resource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Core => f.write_str("REST"),
+            Self::GraphQl => f.write_str("GraphQL"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Budget {
+    limit: Option<u64>,
+    remaining: u64,
+    reset: DateTime<Utc>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    core: Option<Budget>,
+    graphql: Option<Budget>,
+    /// Set after a secondary limit; every request waits until then
+    paused_until: Option<Instant>,
+    /// GraphQL points spent, as reported by the `rateLimit` object
+    graphql_cost: u64,
+}
+
+impl State {
+    fn budget_mut(&mut self, resource: Resource) -> &mut Option<Budget> {
+        match resource {
+            Resource::Core => &mut self.core,
+            Resource::GraphQl => &mut self.graphql,
+        }
+    }
+}
+
+/// Rate limit bookkeeping shared by every clone of a `GitHubClient`.
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    state: Mutex<State>,
+}
+
+impl RateLimiter {
+    /// Wait until it is reasonable to send another request for `resource`:
+    /// after any secondary-limit pause, after the reset if the quota is used
+    /// up, and spaced out when only a little quota is left. Fails instead of
+    /// waiting longer than `MAX_PAUSE`.
+    pub async fn acquire(&self, resource: Resource) -> Result<()> {
+        let delay = {
+            let mut state = self.state.lock().expect("rate limit state poisoned");
+            let now = Instant::now();
+            let paused = state
+                .paused_until
+                .filter(|until| *until > now)
+                .map(|until| until - now);
+
+            let budget_delay = state.budget_mut(resource).as_mut().and_then(|budget| {
+                let until_reset = time_until(budget.reset);
+                if until_reset.is_zero() {
+                    return None;
+                }
+                if budget.remaining == 0 {
+                    return Some(until_reset);
+                }
+                // Claim one request now so concurrent callers see the
+                // reduced budget before the response headers arrive.
+                budget.remaining -= 1;
+                (budget.remaining < THROTTLE_BELOW)
+                    .then(|| until_reset / u32::try_from(budget.remaining + 1).unwrap_or(u32::MAX))
+            });
+
+            paused.max(budget_delay)
+        };
+
+        if let Some(delay) = delay {
+            if delay > MAX_PAUSE {
+                bail!("{resource} quota does not reset soon enough to wait");
+            }
+            if delay >= Duration::from_secs(5) {
+                println!(
+                    "{resource} rate limit reached; waiting {}s",
+                    delay.as_secs()
+                );
+            }
+            sleep(delay).await;
+        }
+        Ok(())
+    }
+
+    /// Record the quota reported by `X-RateLimit-*` response headers.
+    pub fn update(&self, resource: Resource, headers: &HeaderMap) {
+        let header = |name: &str| {
+            headers
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.parse::<u64>().ok())
+        };
+        let (Some(remaining), Some(reset)) =
+            (header("x-ratelimit-remaining"), header("x-ratelimit-reset"))
+        else {
+            return;
+        };
+        let Some(reset) = i64::try_from(reset)
+            .ok()
+            .and_then(|r| Utc.timestamp_opt(r, 0).single())
+        else {
+            return;
+        };
+
+        let mut state = self.state.lock().expect("rate limit state poisoned");
+        *state.budget_mut(resource) = Some(Budget {
+            limit: header("x-ratelimit-limit"),
+            remaining,
+            reset,
+        });
+    }
+
+    /// Record the `rateLimit { cost remaining resetAt }` object of a GraphQL
+    /// response, if the query asked for it.
+    pub fn update_graphql(&self, response: &Value) {
+        let rate_limit = &response["data"]["rateLimit"];
+        let (Some(remaining), Some(reset)) = (
+            rate_limit["remaining"].as_u64(),
+            rate_limit["resetAt"]
+                .as_str()
+                .and_then(|r| r.parse::<DateTime<Utc>>().ok()),
+        ) else {
+            return;
+        };
+
+        let mut state = self.state.lock().expect("rate limit state poisoned");
+        state.graphql_cost += rate_limit["cost"].as_u64().unwrap_or(0);
+        let limit = state.graphql.and_then(|b| b.limit);
+        state.graphql = Some(Budget {
+            limit,
+            remaining,
+            reset,
+        });
+    }
+
+    /// Record a rate-limited response and return how long to back off
+    /// before retrying. Every other request is paused for as long. Returns
+    /// `None` when the wait would be unreasonably long.
+    pub fn backoff(&self, resource: Resource, headers: &HeaderMap) -> Option<Duration> {
+        self.update(resource, headers);
+
+        let retry_after = headers
+            .get("retry-after")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse::<u64>().ok())
+            .map(Duration::from_secs);
+
+        let mut state = self.state.lock().expect("rate limit state poisoned");
+        let exhausted = state
+            .budget_mut(resource)
+            .filter(|b| b.remaining == 0)
+            .map(|b| time_until(b.reset));
+        let delay = retry_after.or(exhausted).unwrap_or(SECONDARY_LIMIT_BACKOFF);
+        if delay > MAX_PAUSE {
+            return None;
+        }
+
+        let until = Instant::now() + delay;
+        if state.paused_until.is_none_or(|p| p < until) {
+            state.paused_until = Some(until);
+        }
+        Some(delay)
+    }
+
+    /// One line per quota seen during the run, for the end-of-run report.
+    pub fn summary(&self) -> Vec<String> {
+        let state = self.state.lock().expect("rate limit state poisoned");
+        let mut lines = Vec::new();
+        for (resource, budget) in [
+            (Resource::Core, state.core),
+            (Resource::GraphQl, state.graphql),
+        ] {
+            let Some(budget) = budget else {
+                continue;
+            };
+            let limit = budget.limit.map_or(String::new(), |l| format!("/{l}"));
+            let cost = if resource == Resource::GraphQl && state.graphql_cost > 0 {
+                format!(" ({} points used)", state.graphql_cost)
+            } else {
+                String::new()
+            };
+            lines.push(format!(
+                "{resource}: {}{limit} remaining, resets at {}{cost}",
+                budget.remaining,
+                budget.reset.with_timezone(&Local).format("%H:%M")
+            ));
+        }
+        lines
+    }
+}
+
+/// Whether a failed response means "slow down" rather than a real error.
+/// GitHub answers both primary and secondary limits with 403 or 429, while
+/// a plain 403 also means a missing permission (e.g. traffic of a repository
+/// the token cannot push to).
+pub fn is_rate_limited(status: StatusCode, headers: &HeaderMap, body: &str) -> bool {
+    match status {
+        StatusCode::TOO_MANY_REQUESTS => true,
+        StatusCode::FORBIDDEN => {
+            headers.contains_key("retry-after")
+                || headers
+                    .get("x-ratelimit-remaining")
+                    .is_some_and(|v| v.as_bytes() == b"0")
+                || body.to_lowercase().contains("rate limit")
+        }
+        _ => false,
+    }
+}
+
+/// GraphQL reports an exhausted quota with status 200 and an error of type
+/// `RATE_LIMITED` instead of an HTTP error.
+pub fn is_graphql_rate_limited(response: &Value) -> bool {
+    response["errors"]
+        .as_array()
+        .is_some_and(|errors| errors.iter().any(|e| e["type"] == "RATE_LIMITED"))
+}
+
+/// Time from now until `instant`, or zero if it has passed.
+fn time_until(instant: DateTime<Utc>) -> Duration {
+    (instant - Utc::now()).to_std().unwrap_or_default()
+}
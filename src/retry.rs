@@ -0,0 +1,105 @@
+use serde_json::Value;
+use tokio::time::{Duration, Instant};
+
+use crate::config::RetryConfig;
+
+/// How often and how long to retry a request that failed for a reason that
+/// is likely to go away on its own.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    initial_delay: Duration,
+    max_delay: Duration,
+    deadline: Duration,
+}
+
+impl RetryPolicy {
+    pub fn from_config(config: &RetryConfig) -> Self {
+        Self {
+            max_attempts: config.max_attempts,
+            initial_delay: Duration::from_millis(config.initial_delay_ms),
+            max_delay: Duration::from_secs(config.max_delay_secs),
+            deadline: Duration::from_secs(config.deadline_secs),
+        }
+    }
+
+    /// Start counting attempts for one request.
+    pub fn start(&self) -> Attempts {
+        Attempts {
+            policy: *self,
+            attempt: 1,
+            started: Instant::now(),
+        }
+    }
+}
+
+/// Attempts made so far for a single request.
+pub struct Attempts {
+    policy: RetryPolicy,
+    attempt: u32,
+    started: Instant,
+}
+
+impl Attempts {
+    /// The attempt about to be made or just made, starting at 1.
+    pub fn number(&self) -> u32 {
+        self.attempt
+    }
+
+    pub fn max(&self) -> u32 {
+        self.policy.max_attempts
+    }
+
+    /// Delay before the next attempt, or `None` once the attempts are used up
+    /// or the wait would run past the deadline.
+    ///
+    /// The delay doubles with every attempt up to the configured maximum, and
+    /// a random half of it is dropped ("equal jitter") so that concurrent
+    /// requests failing together do not all retry at the same moment.
+    pub fn next_delay(&mut self) -> Option<Duration> {
+        if self.attempt >= self.policy.max_attempts {
+            return None;
+        }
+
+        let exponential = self
+            .policy
+            .initial_delay
+            .saturating_mul(1 << (self.attempt - 1).min(16));
+        let capped = exponential.min(self.policy.max_delay);
+        let delay = capped / 2 + capped.mul_f64(fastrand::f64() / 2.0);
+
+        if self.started.elapsed() + delay > self.policy.deadline {
+            return None;
+        }
+
+        self.attempt += 1;
+        Some(delay)
+    }
+}
+
+/// Transport failures worth another try: timeouts, refused or reset
+/// connections, and responses cut off mid-body.
+pub fn is_retryable_error(error: &reqwest::Error) -> bool {
+    error.is_timeout() || error.is_connect() || error.is_request() || error.is_body()
+}
+
+/// Server-side failures worth another try. GitHub's load balancers answer
+/// 502 and 504 when a query runs long, and 503 during incidents.
+pub fn is_retryable_status(status: reqwest::StatusCode) -> bool {
+    status.is_server_error()
+}
+
+/// GraphQL reports server-side timeouts and internal errors with status 200
+/// and an `errors` array instead of an HTTP error.
+pub fn is_retryable_graphql(response: &Value) -> bool {
+    response["errors"].as_array().is_some_and(|errors| {
+        errors.iter().any(|error| {
+            let kind = error["type"].as_str().unwrap_or_default();
+            let message = error["message"].as_str().unwrap_or_default().to_lowercase();
+            matches!(kind, "INTERNAL" | "SERVICE_UNAVAILABLE" | "TIMEOUT")
+                || message.contains("something went wrong")
+                || message.contains("timeout")
+                || message.contains("timed out")
+        })
+    })
+}
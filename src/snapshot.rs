@@ -0,0 +1,76 @@
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::{fs, path::Path};
+
+use crate::{cache::write_atomic, stats::Stats};
+
+/// Version of the snapshot format written by this build.
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+/// Statistics collected by one run, as written to `stats.json`.
+#[derive(Serialize, Deserialize)]
+pub struct Snapshot {
+    pub version: u32,
+    pub generated_at: DateTime<Utc>,
+    pub stats: Stats,
+}
+
+/// The format version of a JSON file, read on its own so that a file from a
+/// newer build can be told apart from a corrupt one. Files from before their
+/// format was versioned count as version 1.
+///
+/// Adding a field with a serde default is backwards compatible and does not
+/// need a new version; renaming, removing or changing the meaning of a field
+/// does.
+pub fn format_version(contents: &str) -> serde_json::Result<u32> {
+    #[derive(Deserialize)]
+    struct Header {
+        version: Option<u32>,
+    }
+
+    let header: Header = serde_json::from_str(contents)?;
+    Ok(header.version.unwrap_or(1))
+}
+
+impl Snapshot {
+    pub fn new(stats: Stats) -> Self {
+        Self {
+            version: SNAPSHOT_VERSION,
+            generated_at: Utc::now(),
+            stats,
+        }
+    }
+
+    pub fn read(path: &Path) -> Result<Self> {
+        let contents = fs::read_to_string(path).with_context(|| {
+            format!(
+                "Failed to read snapshot {}; run `github-stats collect` first",
+                path.display()
+            )
+        })?;
+
+        let version = format_version(&contents)
+            .with_context(|| format!("{} is not a stats snapshot", path.display()))?;
+        if version > SNAPSHOT_VERSION {
+            bail!(
+                "{} has snapshot version {}, but this build only understands up to version {}",
+                path.display(),
+                version,
+                SNAPSHOT_VERSION
+            );
+        }
+
+        serde_json::from_str(&contents)
+            .with_context(|| format!("Failed to parse snapshot {}", path.display()))
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let contents = serde_json::to_string_pretty(self)?;
+        write_atomic(path, contents.as_bytes())
+    }
+}
@@ -1,13 +1,32 @@
-use anyhow::Result;
+use anyhow::{bail, Context, Result};
+use chrono::{Datelike, NaiveDate, NaiveTime, Utc};
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
-use std::{collections::HashMap, fmt::Write, fs, path::Path};
-
-use crate::github_client::GitHubClient;
-
-const RATIO_CACHE_PATH: &str = ".github_stats_cache/ratio_cache.json";
-
-#[derive(Debug, Serialize, Deserialize)]
+use serde_json::{json, Value};
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt::Write,
+    fs,
+    io::ErrorKind,
+    path::PathBuf,
+};
+
+use crate::{
+    cache::{write_atomic, Cache, Freshness},
+    config::{Config, Host},
+    fixtures::Fixtures,
+    github_client::GitHubClient,
+    graphql::GraphQlErrors,
+    models::{
+        self, ContributionYearsData, ContributionsData, ContributorStats, PopularPath,
+        PullRequestState, PullRequestsData, Referrer, ReposData, Repository, RepositoryPermission,
+        TrafficClones, TrafficViews,
+    },
+    retry::RetryPolicy,
+    traffic::RepoTraffic,
+};
+
+#[derive(Debug, Default, Serialize, Deserialize)]
 pub struct Stats {
     pub name: String,
     pub username: String,
@@ -18,7 +37,200 @@ pub struct Stats {
     pub lines_added: u64,
     pub lines_deleted: u64,
     pub total_views: u64,
-    pub languages: HashMap<String, LanguageInfo>,
+    /// Distinct visitors over the last 14 days, added up across repositories
+    #[serde(default)]
+    pub unique_views: u64,
+    /// Views and unique visitors of each repository over the last 14 days,
+    /// most viewed first
+    #[serde(default)]
+    pub repo_views: Vec<RepoViews>,
+    #[serde(default)]
+    pub total_pull_requests: u64,
+    #[serde(default)]
+    pub total_issues: u64,
+    #[serde(default)]
+    pub total_reviews: u64,
+    #[serde(default)]
+    pub total_repositories_created: u64,
+    pub languages: BTreeMap<String, LanguageInfo>,
+    /// Contributions per day across every contribution year, oldest first.
+    #[serde(default)]
+    pub contribution_calendar: Vec<ContributionDay>,
+    #[serde(default)]
+    pub streaks: StreakStats,
+    #[serde(default)]
+    pub pull_requests: PullRequestStats,
+    /// Views recorded in the traffic history, which reaches back further than
+    /// `total_views`
+    #[serde(default)]
+    pub view_history: ViewStats,
+    /// Clones over the last 14 days
+    #[serde(default)]
+    pub total_clones: u64,
+    /// Distinct cloners over the last 14 days, added up across repositories
+    #[serde(default)]
+    pub unique_cloners: u64,
+    #[serde(default)]
+    pub clone_history: CloneStats,
+    /// Sites that sent the most views over the last 14 days, across
+    /// repositories
+    #[serde(default)]
+    pub top_referrers: Vec<Referrer>,
+    /// Most viewed pages over the last 14 days
+    #[serde(default)]
+    pub popular_paths: Vec<PopularPath>,
+    /// Traffic of each repository as reported by this run, for the traffic
+    /// history
+    #[serde(skip)]
+    pub traffic: BTreeMap<String, RepoTraffic>,
+}
+
+impl Stats {
+    /// Add the statistics collected from another host into these. Derived
+    /// figures (streaks, rates and language shares) are recomputed afterwards.
+    pub fn merge(&mut self, other: Stats) {
+        if self.name.is_empty() {
+            self.name = other.name;
+        }
+        if self.username.is_empty() {
+            self.username = other.username;
+        }
+        self.total_stars += other.total_stars;
+        self.total_forks += other.total_forks;
+        self.total_contributions += other.total_contributions;
+        self.total_repos += other.total_repos;
+        self.lines_added += other.lines_added;
+        self.lines_deleted += other.lines_deleted;
+        self.total_views += other.total_views;
+        self.unique_views += other.unique_views;
+        self.repo_views.extend(other.repo_views);
+        self.repo_views.sort_by_key(|repo| Reverse(repo.views));
+        self.total_pull_requests += other.total_pull_requests;
+        self.total_issues += other.total_issues;
+        self.total_reviews += other.total_reviews;
+        self.total_repositories_created += other.total_repositories_created;
+
+        for (name, info) in other.languages {
+            let entry = self.languages.entry(name).or_insert(LanguageInfo {
+                size: 0,
+                occurrences: 0,
+                color: None,
+                percentage: 0.0,
+            });
+            entry.size += info.size;
+            entry.occurrences += info.occurrences;
+            if entry.color.is_none() {
+                entry.color = info.color;
+            }
+        }
+        self.update_percentages();
+
+        let mut days: BTreeMap<NaiveDate, ContributionDay> = self
+            .contribution_calendar
+            .drain(..)
+            .map(|day| (day.date, day))
+            .collect();
+        for day in other.contribution_calendar {
+            match days.get_mut(&day.date) {
+                Some(existing) => {
+                    if day.count > existing.count {
+                        existing.color = day.color;
+                    }
+                    existing.count += day.count;
+                }
+                None => {
+                    days.insert(day.date, day);
+                }
+            }
+        }
+        self.contribution_calendar = days.into_values().collect();
+        self.streaks = StreakStats::from_calendar(&self.contribution_calendar);
+
+        self.pull_requests.merge(&other.pull_requests);
+
+        self.total_clones += other.total_clones;
+        self.unique_cloners += other.unique_cloners;
+        self.top_referrers = top_referrers(self.top_referrers.drain(..).chain(other.top_referrers));
+        self.popular_paths = top_paths(self.popular_paths.drain(..).chain(other.popular_paths));
+        self.traffic.extend(other.traffic);
+    }
+
+    fn update_percentages(&mut self) {
+        let total_size: u64 = self.languages.values().map(|l| l.size).sum();
+        for lang in self.languages.values_mut() {
+            #[allow(clippy::cast_precision_loss)]
+            let percentage = if total_size > 0 {
+                (lang.size as f64 / total_size as f64) * 100.0
+            } else {
+                0.0
+            };
+            lang.percentage = percentage;
+        }
+    }
+}
+
+/// Repository views since the traffic history began.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct ViewStats {
+    pub total: u64,
+    /// Unique visitors of each day, added up: someone who comes back on
+    /// another day counts again
+    #[serde(default)]
+    pub uniques: u64,
+    /// The first day in the history, or `None` while it is empty
+    pub since: Option<NaiveDate>,
+    /// Most viewed first
+    pub repos: Vec<RepoViews>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RepoViews {
+    pub repo: String,
+    pub views: u64,
+    #[serde(default)]
+    pub uniques: u64,
+}
+
+/// Clones since the traffic history began.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct CloneStats {
+    pub total: u64,
+    /// The first day in the history, or `None` while it is empty
+    pub since: Option<NaiveDate>,
+}
+
+/// How many referrers and paths are kept
+const TOP_TRAFFIC_SOURCES: usize = 10;
+
+/// The referrers that sent the most views, adding up the counts of any that
+/// appear more than once.
+fn top_referrers(referrers: impl IntoIterator<Item = Referrer>) -> Vec<Referrer> {
+    let mut combined: BTreeMap<String, Referrer> = BTreeMap::new();
+    for referrer in referrers {
+        match combined.get_mut(&referrer.name) {
+            Some(existing) => {
+                existing.count += referrer.count;
+                existing.uniques += referrer.uniques;
+            }
+            None => {
+                combined.insert(referrer.name.clone(), referrer);
+            }
+        }
+    }
+
+    let mut referrers: Vec<Referrer> = combined.into_values().collect();
+    referrers.sort_by_key(|referrer| Reverse(referrer.count));
+    referrers.truncate(TOP_TRAFFIC_SOURCES);
+    referrers
+}
+
+/// The most viewed paths. Paths start with their repository, so they never
+/// need combining.
+fn top_paths(paths: impl IntoIterator<Item = PopularPath>) -> Vec<PopularPath> {
+    let mut paths: Vec<PopularPath> = paths.into_iter().collect();
+    paths.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.path.cmp(&b.path)));
+    paths.truncate(TOP_TRAFFIC_SOURCES);
+    paths
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -29,6 +241,174 @@ pub struct LanguageInfo {
     pub percentage: f64,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ContributionDay {
+    pub date: NaiveDate,
+    pub count: u32,
+    /// Fill color GitHub uses for this day on its own (light theme) graph
+    pub color: String,
+}
+
+/// Streaks of consecutive days with at least one contribution.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct StreakStats {
+    /// Length of the streak ending on the last collected day. A day without
+    /// contributions yet does not break it, so it counts through yesterday.
+    pub current: u32,
+    pub current_start: Option<NaiveDate>,
+    pub current_end: Option<NaiveDate>,
+    pub longest: u32,
+    pub longest_start: Option<NaiveDate>,
+    pub longest_end: Option<NaiveDate>,
+    pub active_days: u32,
+    pub busiest_day: Option<NaiveDate>,
+    pub busiest_day_count: u32,
+}
+
+impl StreakStats {
+    /// Compute streaks from a calendar sorted by date. Missing dates count as
+    /// days without contributions.
+    pub fn from_calendar(calendar: &[ContributionDay]) -> Self {
+        let mut streaks = Self::default();
+        let mut run = 0u32;
+        let mut run_start: Option<NaiveDate> = None;
+        let mut previous: Option<NaiveDate> = None;
+
+        for day in calendar {
+            let consecutive = previous.is_some_and(|p| p.succ_opt() == Some(day.date));
+            if !consecutive {
+                run = 0;
+            }
+            previous = Some(day.date);
+
+            if day.count == 0 {
+                run = 0;
+                continue;
+            }
+
+            streaks.active_days += 1;
+            if day.count > streaks.busiest_day_count {
+                streaks.busiest_day_count = day.count;
+                streaks.busiest_day = Some(day.date);
+            }
+
+            if run == 0 {
+                run_start = Some(day.date);
+            }
+            run += 1;
+
+            if run > streaks.longest {
+                streaks.longest = run;
+                streaks.longest_start = run_start;
+                streaks.longest_end = Some(day.date);
+            }
+        }
+
+        // Walk back from the last day. Having no contributions on the last day
+        // (yet) does not break the streak, so then it counts from the day before.
+        if let Some(last) = calendar.last() {
+            let today_pending = last.count == 0;
+            let mut expected = if today_pending {
+                last.date.pred_opt()
+            } else {
+                Some(last.date)
+            };
+
+            for day in calendar.iter().rev().skip(usize::from(today_pending)) {
+                if day.count == 0 || Some(day.date) != expected {
+                    break;
+                }
+                if streaks.current == 0 {
+                    streaks.current_end = Some(day.date);
+                }
+                streaks.current += 1;
+                streaks.current_start = Some(day.date);
+                expected = day.date.pred_opt();
+            }
+        }
+
+        streaks
+    }
+}
+
+/// Pull requests authored by the user, split by where they were opened.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct PullRequestStats {
+    /// Pull requests into the user's own repositories
+    pub owned: PullRequestCounts,
+    /// Pull requests into repositories owned by someone else
+    pub external: PullRequestCounts,
+    /// Distinct external repositories with at least one merged pull request
+    pub external_repos_merged: u64,
+    /// Share of closed pull requests that were merged, from 0 to 1
+    pub merge_rate: f64,
+    /// The same share for external pull requests only
+    pub external_merge_rate: f64,
+}
+
+impl PullRequestStats {
+    fn merge(&mut self, other: &PullRequestStats) {
+        self.owned.merge(&other.owned);
+        self.external.merge(&other.external);
+        self.external_repos_merged += other.external_repos_merged;
+        self.update_rates();
+    }
+
+    fn update_rates(&mut self) {
+        let mut all = PullRequestCounts::default();
+        all.merge(&self.owned);
+        all.merge(&self.external);
+        self.merge_rate = all.merge_rate();
+        self.external_merge_rate = self.external.merge_rate();
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct PullRequestCounts {
+    pub merged: u64,
+    /// Closed without being merged
+    pub closed: u64,
+    pub open: u64,
+}
+
+impl PullRequestCounts {
+    fn add(&mut self, state: PullRequestState) {
+        match state {
+            PullRequestState::Merged => self.merged += 1,
+            PullRequestState::Closed => self.closed += 1,
+            PullRequestState::Open => self.open += 1,
+        }
+    }
+
+    fn merge(&mut self, other: &PullRequestCounts) {
+        self.merged += other.merged;
+        self.closed += other.closed;
+        self.open += other.open;
+    }
+
+    /// Merged pull requests as a share of all that were merged or closed.
+    /// Open pull requests have no outcome yet, so they are left out.
+    #[allow(clippy::cast_precision_loss)]
+    fn merge_rate(&self) -> f64 {
+        let resolved = self.merged + self.closed;
+        if resolved == 0 {
+            0.0
+        } else {
+            self.merged as f64 / resolved as f64
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Contributions {
+    total: u64,
+    pull_requests: u64,
+    issues: u64,
+    reviews: u64,
+    repositories_created: u64,
+    calendar: Vec<ContributionDay>,
+}
+
 #[derive(Debug, Clone)]
 struct RepoLanguageEntry {
     name: String,
@@ -39,6 +419,50 @@ struct RepoLanguageEntry {
 #[derive(Debug)]
 struct RepoData {
     languages: Vec<RepoLanguageEntry>,
+    /// Whether the viewer can push to the repository and so read its traffic;
+    /// assumed when GitHub does not say
+    can_push: bool,
+}
+
+/// The traffic endpoints under `/repos/{repo}/traffic/`
+const TRAFFIC_ENDPOINTS: [&str; 4] = ["views", "clones", "popular/referrers", "popular/paths"];
+
+/// Traffic over the last 14 days, as collected from one host.
+#[derive(Default)]
+struct Traffic {
+    views: u64,
+    unique_views: u64,
+    repo_views: Vec<RepoViews>,
+    clones: u64,
+    unique_cloners: u64,
+    repos: BTreeMap<String, RepoTraffic>,
+}
+
+/// Version of the ratio cache format written by this build.
+const RATIO_CACHE_VERSION: u32 = 1;
+
+/// Contribution ratios saved by earlier runs, for repositories whose
+/// statistics GitHub does not return this time.
+#[derive(Deserialize)]
+struct RatioCache {
+    ratios: HashMap<String, f64>,
+}
+
+impl RatioCache {
+    /// Files written before the format was versioned hold the bare map.
+    fn parse(contents: &str) -> Result<HashMap<String, f64>> {
+        let value: Value = serde_json::from_str(contents).context("corrupt file")?;
+        match value.get("version") {
+            None => serde_json::from_value(value).context("corrupt file"),
+            Some(version) if *version == RATIO_CACHE_VERSION => {
+                let cache: Self = serde_json::from_value(value).context("corrupt file")?;
+                Ok(cache.ratios)
+            }
+            Some(version) => {
+                bail!("format version {version}, but this build uses version {RATIO_CACHE_VERSION}")
+            }
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -56,48 +480,89 @@ pub struct StatsCollector {
     excluded_repos: Vec<String>,
     excluded_langs: Vec<String>,
     exclude_forked: bool,
+    strict: bool,
+    /// Whether GraphQL responses are cached
+    cache_graphql: bool,
+    ratio_cache_path: PathBuf,
+    /// Put in front of repository names in the traffic history, so that
+    /// repositories of other hosts are kept apart
+    traffic_prefix: String,
 }
 
 impl StatsCollector {
-    pub fn new(
-        username: &str,
-        access_token: String,
-        excluded_repos: Vec<String>,
-        excluded_langs: &[String],
-        exclude_forked: bool,
-    ) -> Self {
-        Self {
-            username: username.to_string(),
-            client: GitHubClient::new(access_token, 25),
-            excluded_repos,
-            excluded_langs: excluded_langs.iter().map(|s| s.to_lowercase()).collect(),
-            exclude_forked,
-        }
+    pub fn new(host: &Host, config: &Config) -> Result<Self> {
+        let cache = Cache::open(&config.cache)?;
+
+        let fixtures = config
+            .fixtures
+            .as_ref()
+            .map(|mode| Fixtures::open(mode, host))
+            .transpose()?;
+
+        Ok(Self {
+            username: host.user.clone(),
+            client: GitHubClient::new(
+                host,
+                config.collect.concurrency,
+                RetryPolicy::from_config(&config.retry),
+                cache,
+            )?
+            .with_fixtures(fixtures),
+            excluded_repos: config.collect.exclude_repos.clone(),
+            excluded_langs: config
+                .collect
+                .exclude_langs
+                .iter()
+                .map(|s| s.to_lowercase())
+                .collect(),
+            exclude_forked: config.collect.exclude_forked,
+            strict: config.collect.strict,
+            cache_graphql: config.cache.graphql,
+            ratio_cache_path: config.ratio_cache_path(host),
+            traffic_prefix: if host.primary {
+                String::new()
+            } else {
+                format!("{}/", host.name)
+            },
+        })
+    }
+
+    /// Cache hits and misses while collecting, if the cache was used.
+    pub fn cache_summary(&self) -> Option<String> {
+        self.client.cache_summary()
+    }
+
+    /// Remaining API quota after collecting, one line per rate limit.
+    pub fn rate_limit_summary(&self) -> Vec<String> {
+        self.client.rate_limit_summary()
+    }
+
+    /// Whether the run may be recorded in the run and traffic histories.
+    pub fn persist_history(&self) -> bool {
+        self.client.persist_history()
     }
 
     pub async fn collect_all_stats(&self) -> Result<Stats> {
         let mut stats = Stats {
-            name: String::new(),
             username: self.username.clone(),
-            total_stars: 0,
-            total_forks: 0,
-            total_contributions: 0,
-            total_repos: 0,
-            lines_added: 0,
-            lines_deleted: 0,
-            total_views: 0,
-            languages: HashMap::new(),
+            ..Stats::default()
         };
 
         // Phase 1: Collect repository information and raw language data
         let (repos, repo_languages) = self.collect_repos(&mut stats).await?;
         stats.total_repos = repos.len();
+        let traffic_repos: Vec<String> = repos
+            .iter()
+            .filter(|repo| repo_languages.get(*repo).is_some_and(|data| data.can_push))
+            .cloned()
+            .collect();
 
-        // Phase 2: Fetch contributor stats, views, and contributions in parallel
-        let (contributor_stats, views, contributions) = tokio::join!(
+        // Phase 2: Fetch contributor stats, traffic, contributions, and pull requests in parallel
+        let (contributor_stats, traffic, contributions, pull_requests) = tokio::join!(
             self.fetch_contributor_stats(&repos),
-            self.collect_views(&repos),
-            self.collect_contributions()
+            self.collect_traffic(&traffic_repos),
+            self.collect_contributions(),
+            self.collect_pull_requests()
         );
 
         // Phase 3: Calculate contribution ratios and apply weighted language stats
@@ -109,27 +574,70 @@ impl StatsCollector {
         stats.lines_added = added;
         stats.lines_deleted = deleted;
 
-        stats.total_contributions = contributions?;
-
-        if let Ok(total_views) = views {
-            stats.total_views = total_views;
+        let contributions = contributions?;
+        stats.total_contributions = contributions.total;
+        stats.total_pull_requests = contributions.pull_requests;
+        stats.total_issues = contributions.issues;
+        stats.total_reviews = contributions.reviews;
+        stats.total_repositories_created = contributions.repositories_created;
+        stats.streaks = StreakStats::from_calendar(&contributions.calendar);
+        stats.contribution_calendar = contributions.calendar;
+        stats.pull_requests = pull_requests?;
+
+        if let Ok(traffic) = traffic {
+            stats.total_views = traffic.views;
+            stats.unique_views = traffic.unique_views;
+            stats.repo_views = traffic.repo_views;
+            stats.repo_views.sort_by_key(|repo| Reverse(repo.views));
+            stats.total_clones = traffic.clones;
+            stats.unique_cloners = traffic.unique_cloners;
+            let repos = traffic.repos.values();
+            stats.top_referrers = top_referrers(repos.clone().flat_map(|r| r.referrers.clone()));
+            stats.popular_paths = top_paths(repos.flat_map(|r| r.paths.clone()));
+            stats.traffic = traffic.repos;
         }
 
-        // Calculate language percentages
-        let total_size: u64 = stats.languages.values().map(|l| l.size).sum();
-        for lang in stats.languages.values_mut() {
-            #[allow(clippy::cast_precision_loss)]
-            let percentage = if total_size > 0 {
-                (lang.size as f64 / total_size as f64) * 100.0
-            } else {
-                0.0
-            };
-            lang.percentage = percentage;
-        }
+        stats.update_percentages();
 
         Ok(stats)
     }
 
+    /// Run a GraphQL query and return its `data`. Fields that failed, such as
+    /// repositories behind SAML single sign-on, come back `null` and are
+    /// skipped; each is logged with the reason, or fails the run in strict mode.
+    async fn graphql_query(&self, query: &str) -> Result<Value> {
+        self.graphql_query_with(query, None).await
+    }
+
+    /// Like [`Self::graphql_query`], with a cached response fresh by
+    /// `freshness` rather than by the cache policy of the query, when GraphQL
+    /// responses are cached.
+    async fn graphql_query_with(&self, query: &str, freshness: Option<Freshness>) -> Result<Value> {
+        let response = if self.cache_graphql {
+            self.client.graphql_query_cached(query, freshness).await?
+        } else {
+            self.client.graphql_query(query).await?
+        };
+
+        if !response.errors.is_empty() {
+            if self.strict {
+                return Err(GraphQlErrors(response.errors))
+                    .context("GraphQL response is incomplete (strict mode)");
+            }
+            for error in &response.errors {
+                let path = error.path();
+                let what = if path.is_empty() {
+                    "part of a query"
+                } else {
+                    &path
+                };
+                println!("[graphql] skipping {what}: {}", error.message);
+            }
+        }
+
+        Ok(response.data)
+    }
+
     async fn collect_repos(
         &self,
         stats: &mut Stats,
@@ -141,53 +649,27 @@ impl StatsCollector {
 
         loop {
             let query = Self::build_repos_query(owned_cursor.as_deref(), contrib_cursor.as_deref());
-            let response = self.client.graphql_query(&query).await?;
-
-            let data = &response["data"]["viewer"];
+            let data: ReposData = models::decode(self.graphql_query(&query).await?)?;
+            let viewer = data.viewer;
 
             // Get name
             if stats.name.is_empty() {
-                stats.name = data["name"]
-                    .as_str()
-                    .or_else(|| data["login"].as_str())
-                    .unwrap_or("Unknown")
-                    .to_string();
+                stats.name = viewer.name.unwrap_or(viewer.login);
             }
 
             // Process owned repositories
-            if let Some(owned) = data["repositories"].as_object() {
-                if let Some(nodes) = owned["nodes"].as_array() {
-                    for repo in nodes {
-                        self.process_repo(repo, &mut repos, &mut repo_languages, stats);
-                    }
-                }
-
-                if let Some(page_info) = owned["pageInfo"].as_object() {
-                    if page_info["hasNextPage"].as_bool() == Some(true) {
-                        owned_cursor = page_info["endCursor"].as_str().map(String::from);
-                    } else {
-                        owned_cursor = None;
-                    }
-                }
+            for repo in viewer.repositories.nodes.into_iter().flatten() {
+                self.process_repo(repo, &mut repos, &mut repo_languages, stats);
             }
+            owned_cursor = viewer.repositories.page_info.next();
 
             // Process contributed repositories (if not excluding forked)
             if !self.exclude_forked {
-                if let Some(contrib) = data["repositoriesContributedTo"].as_object() {
-                    if let Some(nodes) = contrib["nodes"].as_array() {
-                        for repo in nodes {
-                            self.process_repo(repo, &mut repos, &mut repo_languages, stats);
-                        }
-                    }
-
-                    if let Some(page_info) = contrib["pageInfo"].as_object() {
-                        if page_info["hasNextPage"].as_bool() == Some(true) {
-                            contrib_cursor = page_info["endCursor"].as_str().map(String::from);
-                        } else {
-                            contrib_cursor = None;
-                        }
-                    }
+                let contributed = viewer.repositories_contributed_to;
+                for repo in contributed.nodes.into_iter().flatten() {
+                    self.process_repo(repo, &mut repos, &mut repo_languages, stats);
                 }
+                contrib_cursor = contributed.page_info.next();
             }
 
             // Check if we need to continue paginating
@@ -201,64 +683,57 @@ impl StatsCollector {
 
     fn process_repo(
         &self,
-        repo: &Value,
+        repo: Repository,
         repos: &mut Vec<String>,
         repo_languages: &mut HashMap<String, RepoData>,
         stats: &mut Stats,
     ) {
-        if repo.is_null() {
-            return;
-        }
-
-        let Some(name) = repo["nameWithOwner"].as_str() else {
-            return;
-        };
+        let name = repo.name_with_owner;
 
         // Skip if excluded
-        if self.excluded_repos.contains(&name.to_string()) || repos.contains(&name.to_string()) {
+        if self.excluded_repos.contains(&name) || repos.contains(&name) {
             return;
         }
 
-        repos.push(name.to_string());
+        repos.push(name.clone());
 
         // Add stars and forks
-        if let Some(stargazers) = repo["stargazers"]["totalCount"].as_u64() {
-            stats.total_stars += stargazers;
-        }
-        if let Some(forks) = repo["forkCount"].as_u64() {
-            stats.total_forks += forks;
-        }
+        stats.total_stars += repo.stargazers.total_count;
+        stats.total_forks += repo.fork_count;
 
         // Collect raw language data (will be weighted later)
         let mut languages = Vec::new();
-        if let Some(edges) = repo["languages"]["edges"].as_array() {
-            for edge in edges {
-                let lang_name = edge["node"]["name"].as_str().unwrap_or("Other");
-                let lang_lower = lang_name.to_lowercase();
-
-                // Always exclude HTML (often autogenerated) and any user-specified languages
-                if lang_lower == "html" || self.excluded_langs.contains(&lang_lower) {
-                    continue;
-                }
+        for edge in repo.languages.edges {
+            let lang_lower = edge.node.name.to_lowercase();
 
-                let size = edge["size"].as_u64().unwrap_or(0);
-                let color = edge["node"]["color"].as_str().map(String::from);
-
-                languages.push(RepoLanguageEntry {
-                    name: lang_name.to_string(),
-                    size,
-                    color,
-                });
+            // Always exclude HTML (often autogenerated) and any user-specified languages
+            if lang_lower == "html" || self.excluded_langs.contains(&lang_lower) {
+                continue;
             }
+
+            languages.push(RepoLanguageEntry {
+                name: edge.node.name,
+                size: edge.size,
+                color: edge.node.color,
+            });
         }
 
-        repo_languages.insert(name.to_string(), RepoData { languages });
+        let can_push = repo
+            .viewer_permission
+            .is_none_or(RepositoryPermission::can_push);
+        repo_languages.insert(
+            name,
+            RepoData {
+                languages,
+                can_push,
+            },
+        );
     }
 
-    async fn collect_contributions(&self) -> Result<u64> {
+    async fn collect_contributions(&self) -> Result<Contributions> {
         // Get contribution years
         let years_query = r"
-        query {
+        query ContributionYears {
             viewer {
                 contributionsCollection {
                     contributionYears
@@ -266,85 +741,186 @@ impl StatsCollector {
             }
         }";
 
-        let response = self.client.graphql_query(years_query).await?;
-        let years = response["data"]["viewer"]["contributionsCollection"]["contributionYears"]
-            .as_array()
-            .ok_or_else(|| anyhow::anyhow!("Failed to get contribution years"))?;
+        let data: ContributionYearsData = models::decode(self.graphql_query(years_query).await?)?;
+        let years = data.viewer.contributions_collection.contribution_years;
 
         if years.is_empty() {
-            return Ok(0);
+            return Ok(Contributions::default());
         }
 
-        // Build query for all years
-        let mut year_queries = String::new();
-        for year in years {
-            if let Some(year_val) = year.as_i64() {
-                write!(
-                    year_queries,
-                    r#"
-                    year{}: contributionsCollection(
-                        from: "{}-01-01T00:00:00Z",
-                        to: "{}-01-01T00:00:00Z"
-                    ) {{
-                        contributionCalendar {{
-                            totalContributions
-                        }}
-                    }}"#,
-                    year_val,
-                    year_val,
-                    year_val + 1
-                )?;
+        // With the GraphQL cache on, past years go in a query of their own
+        // that stays cached for the rest of the year, and only the current
+        // year is refreshed. The list of past years grows every January, so
+        // the old entry then expires rather than being kept forever.
+        let batches = if self.cache_graphql {
+            let now = Utc::now();
+            let year_start = |year| {
+                NaiveDate::from_yo_opt(year, 1)
+                    .unwrap_or_default()
+                    .and_time(NaiveTime::MIN)
+                    .and_utc()
+            };
+            let (past, current) = years.into_iter().partition(|&y| y < now.year());
+            let freshness = Freshness::Between {
+                start: year_start(now.year()),
+                end: year_start(now.year() + 1),
+            };
+            vec![(past, Some(freshness)), (current, None)]
+        } else {
+            vec![(years, None)]
+        };
+
+        let mut collections = BTreeMap::new();
+        for (years, freshness) in batches {
+            if years.is_empty() {
+                continue;
             }
+            let query = Self::build_contributions_query(&years)?;
+            let data: ContributionsData =
+                models::decode(self.graphql_query_with(&query, freshness).await?)?;
+            collections.extend(data.viewer);
         }
 
-        let query = format!(
-            r"
-            query {{
-                viewer {{
-                    {year_queries}
-                }}
-            }}"
-        );
+        // The calendar for the current year runs to the end of the year, so
+        // drop days that have not happened yet
+        let today = Utc::now().date_naive();
+        let mut contributions = Contributions::default();
+        let mut days = BTreeMap::new();
+        for year in collections.into_values() {
+            let calendar = year.contribution_calendar;
+            contributions.total += calendar.total_contributions;
+            contributions.pull_requests += year.total_pull_request_contributions;
+            contributions.issues += year.total_issue_contributions;
+            contributions.reviews += year.total_pull_request_review_contributions;
+            contributions.repositories_created += year.total_repository_contributions;
+
+            let calendar_days = calendar
+                .weeks
+                .into_iter()
+                .flat_map(|week| week.contribution_days)
+                .filter(|day| day.date <= today);
+            for day in calendar_days {
+                days.insert(
+                    day.date,
+                    ContributionDay {
+                        date: day.date,
+                        count: day.contribution_count,
+                        color: day.color,
+                    },
+                );
+            }
+        }
 
-        let response = self.client.graphql_query(&query).await?;
-        let viewer = &response["data"]["viewer"];
+        contributions.calendar = days.into_values().collect();
+        Ok(contributions)
+    }
 
-        let mut total = 0u64;
-        if let Some(obj) = viewer.as_object() {
-            for (_key, value) in obj {
-                if let Some(contribs) = value["contributionCalendar"]["totalContributions"].as_u64()
-                {
-                    total += contribs;
+    async fn collect_pull_requests(&self) -> Result<PullRequestStats> {
+        let mut pull_requests = PullRequestStats::default();
+        let mut external_repos_merged = HashSet::new();
+        let mut cursor: Option<String> = None;
+
+        loop {
+            let query = Self::build_pull_requests_query(cursor.as_deref());
+            let data: PullRequestsData = models::decode(self.graphql_query(&query).await?)?;
+            let connection = data.viewer.pull_requests;
+
+            for pr in connection.nodes.into_iter().flatten() {
+                let repo = pr.repository.name_with_owner;
+                if self.excluded_repos.contains(&repo) {
+                    continue;
+                }
+
+                if self.is_owned_repo(&repo) {
+                    pull_requests.owned.add(pr.state);
+                } else {
+                    pull_requests.external.add(pr.state);
+                    if pr.state == PullRequestState::Merged {
+                        external_repos_merged.insert(repo);
+                    }
                 }
             }
+
+            cursor = connection.page_info.next();
+            if cursor.is_none() {
+                break;
+            }
         }
 
-        Ok(total)
+        pull_requests.external_repos_merged = external_repos_merged.len() as u64;
+        pull_requests.update_rates();
+
+        Ok(pull_requests)
     }
 
-    async fn collect_views(&self, repos: &[String]) -> Result<u64> {
+    /// Views, clones, referrers and popular paths of every repository over
+    /// the last 14 days, fetched in one batch. Repositories whose traffic the
+    /// token may not read are left out.
+    async fn collect_traffic(&self, repos: &[String]) -> Result<Traffic> {
         let paths: Vec<String> = repos
             .iter()
-            .map(|repo| format!("/repos/{repo}/traffic/views"))
+            .flat_map(|repo| {
+                TRAFFIC_ENDPOINTS
+                    .iter()
+                    .map(move |endpoint| format!("/repos/{repo}/traffic/{endpoint}"))
+            })
             .collect();
 
         let results = self.client.rest_get_batch(paths).await;
 
-        let mut total_views = 0u64;
-        for (_path, result) in results {
-            if let Ok(traffic) = result {
-                if let Some(views) = traffic["views"].as_array() {
-                    for view in views {
-                        total_views += view["count"].as_u64().unwrap_or(0);
+        let mut traffic = Traffic::default();
+        for (path, result) in results {
+            let Ok(value) = result else {
+                continue;
+            };
+            let Some((repo, endpoint)) = path
+                .strip_prefix("/repos/")
+                .and_then(|rest| rest.split_once("/traffic/"))
+            else {
+                continue;
+            };
+            let reported = traffic
+                .repos
+                .entry(format!("{}{repo}", self.traffic_prefix))
+                .or_default();
+
+            let decoded = match endpoint {
+                "views" => models::decode::<TrafficViews>(value).map(|views| {
+                    traffic.views += views.count;
+                    traffic.unique_views += views.uniques;
+                    if views.count > 0 {
+                        traffic.repo_views.push(RepoViews {
+                            repo: format!("{}{repo}", self.traffic_prefix),
+                            views: views.count,
+                            uniques: views.uniques,
+                        });
                     }
+                    reported.views = views.views;
+                }),
+                "clones" => models::decode::<TrafficClones>(value).map(|clones| {
+                    traffic.clones += clones.count;
+                    traffic.unique_cloners += clones.uniques;
+                    reported.clones = clones.clones;
+                }),
+                "popular/referrers" => models::decode::<Vec<Referrer>>(value)
+                    .map(|referrers| reported.referrers = referrers),
+                "popular/paths" => {
+                    models::decode::<Vec<PopularPath>>(value).map(|paths| reported.paths = paths)
                 }
+                _ => Ok(()),
+            };
+            if let Err(e) = decoded {
+                println!("  [traffic] {path}: {e}");
             }
         }
 
-        Ok(total_views)
+        Ok(traffic)
     }
 
-    async fn fetch_contributor_stats(&self, repos: &[String]) -> HashMap<String, Value> {
+    async fn fetch_contributor_stats(
+        &self,
+        repos: &[String],
+    ) -> HashMap<String, Vec<ContributorStats>> {
         let paths: Vec<String> = repos
             .iter()
             .map(|repo| format!("/repos/{repo}/stats/contributors"))
@@ -354,39 +930,61 @@ impl StatsCollector {
 
         let mut stats_map = HashMap::new();
         for (path, result) in results {
-            if let Ok(data) = result {
-                // Extract repo name from path: /repos/{owner}/{repo}/stats/contributors
-                let parts: Vec<&str> = path.split('/').collect();
-                if parts.len() >= 4 {
-                    let repo_name = format!("{}/{}", parts[2], parts[3]);
-                    stats_map.insert(repo_name, data);
+            let Ok(data) = result else {
+                continue;
+            };
+            let contributors = match models::decode::<Vec<ContributorStats>>(data) {
+                Ok(contributors) => contributors,
+                Err(e) => {
+                    println!("  [stats] {path}: {e}");
+                    continue;
                 }
+            };
+            // Extract repo name from path: /repos/{owner}/{repo}/stats/contributors
+            let parts: Vec<&str> = path.split('/').collect();
+            if parts.len() >= 4 {
+                let repo_name = format!("{}/{}", parts[2], parts[3]);
+                stats_map.insert(repo_name, contributors);
             }
         }
 
         stats_map
     }
 
-    fn load_ratio_cache() -> HashMap<String, f64> {
-        let path = Path::new(RATIO_CACHE_PATH);
-        if !path.exists() {
-            return HashMap::new();
-        }
+    /// Ratios saved by earlier runs. An unusable file is reported and
+    /// ignored; the next save replaces it.
+    fn load_ratio_cache(&self) -> HashMap<String, f64> {
+        let path = &self.ratio_cache_path;
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == ErrorKind::NotFound => return HashMap::new(),
+            Err(e) => {
+                println!("  [ratios] cannot read {}: {e}", path.display());
+                return HashMap::new();
+            }
+        };
 
-        fs::read_to_string(path)
-            .ok()
-            .and_then(|contents| serde_json::from_str(&contents).ok())
-            .unwrap_or_default()
+        match RatioCache::parse(&contents) {
+            Ok(ratios) => ratios,
+            Err(e) => {
+                println!("  [ratios] ignoring {}: {e:#}", path.display());
+                HashMap::new()
+            }
+        }
     }
 
-    fn save_ratio_cache(ratios: &HashMap<String, f64>) {
+    fn save_ratio_cache(&self, ratios: &HashMap<String, f64>) {
         // Ensure cache directory exists
-        if let Some(parent) = Path::new(RATIO_CACHE_PATH).parent() {
+        if let Some(parent) = self.ratio_cache_path.parent() {
             fs::create_dir_all(parent).ok();
         }
 
-        if let Ok(contents) = serde_json::to_string_pretty(ratios) {
-            fs::write(RATIO_CACHE_PATH, contents).ok();
+        let cache = json!({ "version": RATIO_CACHE_VERSION, "ratios": ratios });
+        let result = serde_json::to_string_pretty(&cache)
+            .map_err(anyhow::Error::from)
+            .and_then(|contents| write_atomic(&self.ratio_cache_path, contents.as_bytes()));
+        if let Err(e) = result {
+            println!("  [ratios] {e:#}");
         }
     }
 
@@ -396,10 +994,10 @@ impl StatsCollector {
 
     fn calculate_contribution_ratios(
         &self,
-        contributor_stats: &HashMap<String, Value>,
+        contributor_stats: &HashMap<String, Vec<ContributorStats>>,
         all_repos: &[String],
     ) -> HashMap<String, f64> {
-        let cached_ratios = Self::load_ratio_cache();
+        let cached_ratios = self.load_ratio_cache();
         let mut ratios = HashMap::new();
         let mut calculated_count = 0u32;
         let mut from_cache_count = 0u32;
@@ -451,7 +1049,9 @@ impl StatsCollector {
         }
 
         // Save updated ratios to cache
-        Self::save_ratio_cache(&ratios);
+        if self.client.persist_history() {
+            self.save_ratio_cache(&ratios);
+        }
 
         let total = calculated_count + from_cache_count + fallback_count;
         println!(
@@ -461,12 +1061,8 @@ impl StatsCollector {
         ratios
     }
 
-    fn calculate_single_ratio(&self, contributors: &Value) -> RatioResult {
-        let Some(contrib_array) = contributors.as_array() else {
-            return RatioResult::FallbackNoStats;
-        };
-
-        if contrib_array.is_empty() {
+    fn calculate_single_ratio(&self, contributors: &[ContributorStats]) -> RatioResult {
+        if contributors.is_empty() {
             return RatioResult::FallbackEmptyStats;
         }
 
@@ -474,18 +1070,13 @@ impl StatsCollector {
         let mut total_added: u64 = 0;
         let mut found_user = false;
 
-        for contributor in contrib_array {
-            let added: u64 = contributor["weeks"].as_array().map_or(0, |weeks| {
-                weeks.iter().map(|w| w["a"].as_u64().unwrap_or(0)).sum()
-            });
-
+        for contributor in contributors {
+            let added = contributor.lines_added();
             total_added += added;
 
-            if let Some(author) = contributor["author"]["login"].as_str() {
-                if author.eq_ignore_ascii_case(&self.username) {
-                    my_added = added;
-                    found_user = true;
-                }
+            if contributor.is(&self.username) {
+                my_added = added;
+                found_user = true;
             }
         }
 
@@ -537,29 +1128,96 @@ impl StatsCollector {
         }
     }
 
-    fn extract_lines_changed(&self, contributor_stats: &HashMap<String, Value>) -> (u64, u64) {
+    fn extract_lines_changed(
+        &self,
+        contributor_stats: &HashMap<String, Vec<ContributorStats>>,
+    ) -> (u64, u64) {
         contributor_stats
             .values()
-            .filter_map(Value::as_array)
             .flatten()
-            .filter(|c| {
-                c["author"]["login"]
-                    .as_str()
-                    .is_some_and(|author| author.eq_ignore_ascii_case(&self.username))
-            })
-            .filter_map(|c| c["weeks"].as_array())
-            .flatten()
-            .fold((0u64, 0u64), |(added, deleted), week| {
-                (
-                    added + week["a"].as_u64().unwrap_or(0),
-                    deleted + week["d"].as_u64().unwrap_or(0),
-                )
+            .filter(|c| c.is(&self.username))
+            .fold((0u64, 0u64), |(added, deleted), c| {
+                (added + c.lines_added(), deleted + c.lines_deleted())
             })
     }
 
+    /// One aliased contributions collection per year, `year2021`, `year2022`, ...
+    fn build_contributions_query(years: &[i32]) -> Result<String> {
+        let mut year_queries = String::new();
+        for year in years {
+            write!(
+                year_queries,
+                r#"
+                year{}: contributionsCollection(
+                    from: "{}-01-01T00:00:00Z",
+                    to: "{}-01-01T00:00:00Z"
+                ) {{
+                    totalPullRequestContributions
+                    totalIssueContributions
+                    totalPullRequestReviewContributions
+                    totalRepositoryContributions
+                    contributionCalendar {{
+                        totalContributions
+                        weeks {{
+                            contributionDays {{
+                                date
+                                contributionCount
+                                color
+                            }}
+                        }}
+                    }}
+                }}"#,
+                year,
+                year,
+                year + 1
+            )?;
+        }
+
+        Ok(format!(
+            r"
+            query Contributions {{
+                viewer {{
+                    {year_queries}
+                }}
+                rateLimit {{
+                    cost
+                    remaining
+                    resetAt
+                }}
+            }}"
+        ))
+    }
+
+    fn build_pull_requests_query(cursor: Option<&str>) -> String {
+        format!(
+            r"query PullRequests {{
+                viewer {{
+                    pullRequests(first: 100, after: {}) {{
+                        pageInfo {{
+                            hasNextPage
+                            endCursor
+                        }}
+                        nodes {{
+                            state
+                            repository {{
+                                nameWithOwner
+                            }}
+                        }}
+                    }}
+                }}
+                rateLimit {{
+                    cost
+                    remaining
+                    resetAt
+                }}
+            }}",
+            cursor.map_or_else(|| "null".to_string(), |c| format!(r#""{c}""#))
+        )
+    }
+
     fn build_repos_query(owned_cursor: Option<&str>, contrib_cursor: Option<&str>) -> String {
         format!(
-            r"{{
+            r"query Repositories {{
                 viewer {{
                     login,
                     name,
@@ -579,6 +1237,7 @@ impl StatsCollector {
                                 totalCount
                             }}
                             forkCount
+                            viewerPermission
                             languages(first: 10, orderBy: {{field: SIZE, direction: DESC}}) {{
                                 edges {{
                                     size
@@ -607,6 +1266,7 @@ impl StatsCollector {
                                 totalCount
                             }}
                             forkCount
+                            viewerPermission
                             languages(first: 10, orderBy: {{field: SIZE, direction: DESC}}) {{
                                 edges {{
                                     size
@@ -619,6 +1279,11 @@ impl StatsCollector {
                         }}
                     }}
                 }}
+                rateLimit {{
+                    cost
+                    remaining
+                    resetAt
+                }}
             }}",
             owned_cursor.map_or_else(|| "null".to_string(), |c| format!(r#""{c}""#)),
             contrib_cursor.map_or_else(|| "null".to_string(), |c| format!(r#""{c}""#))
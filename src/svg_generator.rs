@@ -1,16 +1,58 @@
-use anyhow::Result;
-use std::{fmt::Write, fs, path::Path};
+use anyhow::{Context, Result};
+use chrono::{Datelike, Duration, NaiveDate};
+use std::{cmp::Reverse, collections::HashMap, fmt::Write, fs};
 
-use crate::stats::{LanguageInfo, Stats};
+use crate::{
+    config::{CardConfig, RenderConfig},
+    history::{History, Metric},
+    snapshot::Snapshot,
+    stats::{LanguageInfo, Stats},
+};
 
-const MAX_LANGUAGES: usize = 12;
+// Size of the box each sparkline on the trends card is scaled to fit
+const SPARKLINE_WIDTH: f64 = 180.0;
+const SPARKLINE_HEIGHT: f64 = 36.0;
+// Keeps the stroke from being clipped at the top and bottom of the box
+const SPARKLINE_PADDING: f64 = 2.0;
+
+// Calendar heatmap geometry, matching GitHub's own contribution graph
+const CALENDAR_CELL: i64 = 10;
+const CALENDAR_STEP: i64 = 12;
+// Month labels closer together than this many weeks would overlap
+const CALENDAR_MIN_LABEL_WEEKS: i64 = 3;
+
+// Referrers listed on the traffic card
+const TRAFFIC_REFERRERS: usize = 3;
 
 pub struct SvgGenerator;
 
 impl SvgGenerator {
-    pub fn generate_overview(stats: &Stats) -> Result<()> {
+    pub fn generate_overview(
+        stats: &Stats,
+        history: &History,
+        config: &RenderConfig,
+    ) -> Result<()> {
         // Read template
-        let template = fs::read_to_string("templates/overview.svg")?;
+        let template = read_template(&config.overview)?;
+
+        let prs = &stats.pull_requests;
+        // GitHub only reports two weeks of views; the traffic history, once
+        // it has any, reaches back further
+        let (recorded_views, unique_views, views_period) = match stats.view_history.since {
+            Some(since) => (
+                stats.view_history.total,
+                stats.view_history.uniques,
+                format!("since {}", since.format("%b %-d, %Y")),
+            ),
+            None => (
+                stats.total_views,
+                stats.unique_views,
+                "past two weeks".to_string(),
+            ),
+        };
+        let window = Duration::days(i64::from(config.delta_days));
+        let delta =
+            |metric: Metric| format_delta(history.change_over(window, metric), config.delta_days);
 
         // Replace placeholders
         let output = template
@@ -25,26 +67,60 @@ impl SvgGenerator {
                 "{{ lines_changed }}",
                 &format_number(stats.lines_added + stats.lines_deleted),
             )
+            .replace(
+                "{{ pull_requests }}",
+                &format_number(stats.total_pull_requests),
+            )
+            .replace(
+                "{{ merged_pull_requests }}",
+                &format_number(prs.owned.merged + prs.external.merged),
+            )
+            .replace("{{ merge_rate }}", &format_percent(prs.merge_rate))
+            .replace(
+                "{{ external_merged_pull_requests }}",
+                &format_number(prs.external.merged),
+            )
+            .replace(
+                "{{ external_merge_rate }}",
+                &format_percent(prs.external_merge_rate),
+            )
+            .replace(
+                "{{ external_repos_merged }}",
+                &format_number(prs.external_repos_merged),
+            )
+            .replace("{{ issues }}", &format_number(stats.total_issues))
+            .replace("{{ reviews }}", &format_number(stats.total_reviews))
+            .replace(
+                "{{ repositories_created }}",
+                &format_number(stats.total_repositories_created),
+            )
             .replace("{{ views }}", &format_number(stats.total_views))
-            .replace("{{ repos }}", &format_number(stats.total_repos as u64));
-
-        // Create output directory if it doesn't exist
-        if !Path::new("generated").exists() {
-            fs::create_dir("generated")?;
-        }
+            .replace("{{ recorded_views }}", &format_number(recorded_views))
+            .replace("{{ unique_views }}", &format_number(unique_views))
+            .replace("{{ views_period }}", &views_period)
+            .replace("{{ repos }}", &format_number(stats.total_repos as u64))
+            .replace("{{ stars_delta }}", &delta(|s| s.total_stars))
+            .replace("{{ forks_delta }}", &delta(|s| s.total_forks))
+            .replace(
+                "{{ contributions_delta }}",
+                &delta(|s| s.total_contributions),
+            )
+            .replace(
+                "{{ lines_changed_delta }}",
+                &delta(|s| s.lines_added + s.lines_deleted),
+            )
+            .replace("{{ repos_delta }}", &delta(|s| s.total_repos as u64));
 
-        // Write output
-        fs::write("generated/overview.svg", output)?;
-        Ok(())
+        write_output(&config.overview, &output)
     }
 
-    pub fn generate_languages(stats: &Stats) -> Result<()> {
+    pub fn generate_languages(stats: &Stats, config: &RenderConfig) -> Result<()> {
         // Read template
-        let template = fs::read_to_string("templates/languages.svg")?;
+        let template = read_template(&config.languages)?;
 
         // Sort languages by size
         let mut languages: Vec<(&String, &LanguageInfo)> = stats.languages.iter().collect();
-        languages.sort_by(|a, b| b.1.size.cmp(&a.1.size));
+        languages.sort_by_key(|(_, info)| Reverse(info.size));
 
         // Generate progress bar and language list
         let mut progress = String::new();
@@ -60,7 +136,7 @@ impl SvgGenerator {
         // Maximum rows: 5 (118px / 21px = 5.6)
         // With wrapping, we need to limit total to avoid overflow
 
-        for (i, (name, info)) in languages.iter().take(MAX_LANGUAGES).enumerate() {
+        for (i, (name, info)) in languages.iter().take(config.max_languages).enumerate() {
             let color = info.color.as_deref().unwrap_or("#000000");
 
             write!(
@@ -92,12 +168,353 @@ fill-rule="evenodd" d="M8 4a4 4 0 100 8 4 4 0 000-8z"></path></svg>
             .replace("{{ progress }}", &progress)
             .replace("{{ lang_list }}", &lang_list);
 
-        // Write output
-        fs::write("generated/languages.svg", output)?;
-        Ok(())
+        write_output(&config.languages, &output)
+    }
+
+    pub fn generate_trends(stats: &Stats, history: &History, config: &RenderConfig) -> Result<()> {
+        // Read template
+        let template = read_template(&config.trends)?;
+
+        let window = Duration::days(i64::from(config.trend_days));
+        let records = history.window(window);
+        let delta =
+            |metric: Metric| format_delta(history.change_over(window, metric), config.trend_days);
+        let lines_changed: Metric = |s| s.lines_added + s.lines_deleted;
+
+        // Replace placeholders
+        let output = template
+            .replace("{{ name }}", &stats.name)
+            .replace("{{ window }}", &config.trend_days.to_string())
+            .replace("{{ stars }}", &format_number(stats.total_stars))
+            .replace("{{ stars_delta }}", &delta(|s| s.total_stars))
+            .replace(
+                "{{ stars_sparkline }}",
+                &sparkline(records, |s| s.total_stars)?,
+            )
+            .replace(
+                "{{ contributions }}",
+                &format_number(stats.total_contributions),
+            )
+            .replace(
+                "{{ contributions_delta }}",
+                &delta(|s| s.total_contributions),
+            )
+            .replace(
+                "{{ contributions_sparkline }}",
+                &sparkline(records, |s| s.total_contributions)?,
+            )
+            .replace("{{ lines_changed }}", &format_number(lines_changed(stats)))
+            .replace("{{ lines_changed_delta }}", &delta(lines_changed))
+            .replace(
+                "{{ lines_changed_sparkline }}",
+                &sparkline(records, lines_changed)?,
+            );
+
+        write_output(&config.trends, &output)
+    }
+
+    pub fn generate_calendar(stats: &Stats, config: &RenderConfig) -> Result<()> {
+        let Some(last_day) = stats.contribution_calendar.last().map(|d| d.date) else {
+            println!("  [calendar] snapshot has no per-day contributions, skipping calendar card");
+            return Ok(());
+        };
+
+        // Read template
+        let template = read_template(&config.calendar)?;
+
+        let (start, end, period) = match config.calendar_year {
+            Some(year) => (
+                NaiveDate::from_ymd_opt(year, 1, 1).context("Invalid calendar year")?,
+                NaiveDate::from_ymd_opt(year, 12, 31)
+                    .context("Invalid calendar year")?
+                    .min(last_day),
+                format!("in {year}"),
+            ),
+            None => (
+                last_day - Duration::days(364),
+                last_day,
+                "in the last year".to_string(),
+            ),
+        };
+
+        let counts: HashMap<NaiveDate, u32> = stats
+            .contribution_calendar
+            .iter()
+            .filter(|d| d.date >= start && d.date <= end)
+            .map(|d| (d.date, d.count))
+            .collect();
+        let max = counts.values().copied().max().unwrap_or(0);
+        let total: u64 = counts.values().map(|&c| u64::from(c)).sum();
+
+        // Columns are weeks starting on Sunday, rows are days of the week
+        let grid_start = start - Duration::days(i64::from(start.weekday().num_days_from_sunday()));
+        let mut days = String::new();
+        let mut months = String::new();
+        let mut last_label_week = -CALENDAR_MIN_LABEL_WEEKS;
+
+        for date in start.iter_days().take_while(|d| *d <= end) {
+            let week = (date - grid_start).num_days() / 7;
+            let weekday = i64::from(date.weekday().num_days_from_sunday());
+            let count = counts.get(&date).copied().unwrap_or(0);
+
+            write!(
+                days,
+                r#"<rect x="{}" y="{}" width="{CALENDAR_CELL}" height="{CALENDAR_CELL}" rx="2" class="day level-{}"><title>{} contribution{} on {}</title></rect>"#,
+                week * CALENDAR_STEP,
+                weekday * CALENDAR_STEP,
+                contribution_level(count, max),
+                format_number(u64::from(count)),
+                if count == 1 { "" } else { "s" },
+                date.format("%B %-d, %Y"),
+            )?;
+
+            // Label each month above the first full week that starts in it
+            if weekday == 0 && date.day() <= 7 && week - last_label_week >= CALENDAR_MIN_LABEL_WEEKS
+            {
+                write!(
+                    months,
+                    r#"<text x="{}" y="-8" class="month">{}</text>"#,
+                    week * CALENDAR_STEP,
+                    date.format("%b")
+                )?;
+                last_label_week = week;
+            }
+        }
+
+        // Replace placeholders
+        let output = template
+            .replace("{{ name }}", &stats.name)
+            .replace("{{ total }}", &format_number(total))
+            .replace("{{ period }}", &period)
+            .replace("{{ months }}", &months)
+            .replace("{{ days }}", &days);
+
+        write_output(&config.calendar, &output)
+    }
+
+    pub fn generate_streaks(stats: &Stats, config: &RenderConfig) -> Result<()> {
+        // Read template
+        let template = read_template(&config.streaks)?;
+
+        let streaks = &stats.streaks;
+        let since = stats
+            .contribution_calendar
+            .iter()
+            .find(|d| d.count > 0)
+            .map(|d| format!("Since {}", d.date.format("%b %-d, %Y")))
+            .unwrap_or_default();
+        let busiest_day = streaks.busiest_day.map_or_else(String::new, |date| {
+            format!(
+                "Busiest day: {} contributions on {}",
+                format_number(u64::from(streaks.busiest_day_count)),
+                date.format("%B %-d, %Y")
+            )
+        });
+
+        // Replace placeholders
+        let output = template
+            .replace("{{ name }}", &stats.name)
+            .replace(
+                "{{ active_days }}",
+                &format_number(u64::from(streaks.active_days)),
+            )
+            .replace("{{ active_since }}", &since)
+            .replace(
+                "{{ current_streak }}",
+                &format_number(u64::from(streaks.current)),
+            )
+            .replace(
+                "{{ current_streak_range }}",
+                &format_date_range(streaks.current_start, streaks.current_end),
+            )
+            .replace(
+                "{{ longest_streak }}",
+                &format_number(u64::from(streaks.longest)),
+            )
+            .replace(
+                "{{ longest_streak_range }}",
+                &format_date_range(streaks.longest_start, streaks.longest_end),
+            )
+            .replace("{{ busiest_day }}", &busiest_day);
+
+        write_output(&config.streaks, &output)
+    }
+
+    pub fn generate_traffic(stats: &Stats, config: &RenderConfig) -> Result<()> {
+        // Read template
+        let template = read_template(&config.traffic)?;
+
+        let (recorded_clones, clones_period) = match stats.clone_history.since {
+            Some(since) => (
+                stats.clone_history.total,
+                format!("Since {}", since.format("%b %-d, %Y")),
+            ),
+            None => (stats.total_clones, "Past two weeks".to_string()),
+        };
+        let (recorded_views, unique_views, views_period) = match stats.view_history.since {
+            Some(since) => (
+                stats.view_history.total,
+                stats.view_history.uniques,
+                format!("Since {}", since.format("%b %-d, %Y")),
+            ),
+            None => (
+                stats.total_views,
+                stats.unique_views,
+                "Past two weeks".to_string(),
+            ),
+        };
+
+        // One row per referrer, below the "Top referrers" heading
+        let mut referrers = String::new();
+        for (i, referrer) in stats
+            .top_referrers
+            .iter()
+            .take(TRAFFIC_REFERRERS)
+            .enumerate()
+        {
+            let y = 190 + i * 20;
+            write!(
+                referrers,
+                r#"
+<text x="25" y="{y}" class="referrer">{}</text>
+<text x="330" y="{y}" class="referrer-count">{} views, {} unique</text>"#,
+                escape_xml(&referrer.name),
+                format_number(referrer.count),
+                format_number(referrer.uniques)
+            )?;
+        }
+        if referrers.is_empty() {
+            referrers =
+                r#"<text x="25" y="190" class="referrer">No referrers in the past two weeks</text>"#
+                    .to_string();
+        }
+
+        // Replace placeholders
+        let output = template
+            .replace("{{ name }}", &stats.name)
+            .replace("{{ recorded_clones }}", &format_number(recorded_clones))
+            .replace("{{ clones_period }}", &clones_period)
+            .replace("{{ unique_cloners }}", &format_number(stats.unique_cloners))
+            .replace("{{ recorded_views }}", &format_number(recorded_views))
+            .replace("{{ unique_views }}", &format_number(unique_views))
+            .replace("{{ views_period }}", &views_period)
+            .replace("{{ referrers }}", &referrers);
+
+        write_output(&config.traffic, &output)
     }
 }
 
+/// Bucket a day into one of GitHub's five heatmap shades, relative to the
+/// busiest day in view.
+fn contribution_level(count: u32, max: u32) -> u32 {
+    if count == 0 || max == 0 {
+        0
+    } else {
+        (count * 4).div_ceil(max).clamp(1, 4)
+    }
+}
+
+/// Draw `metric` across `records` as a line with a shaded area underneath,
+/// scaled to fill a `SPARKLINE_WIDTH` by `SPARKLINE_HEIGHT` box.
+///
+/// Runs are placed by timestamp rather than index, so gaps between runs show
+/// up as gaps. A single run is drawn as a flat line.
+#[allow(clippy::cast_precision_loss)]
+fn sparkline(records: &[Snapshot], metric: Metric) -> Result<String> {
+    let (Some(first), Some(last)) = (records.first(), records.last()) else {
+        return Ok(String::new());
+    };
+
+    let values: Vec<f64> = records.iter().map(|r| metric(&r.stats) as f64).collect();
+    let min = values.iter().copied().fold(f64::INFINITY, f64::min);
+    let max = values.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    let duration = (last.generated_at - first.generated_at).num_seconds() as f64;
+
+    let usable_height = SPARKLINE_HEIGHT - 2.0 * SPARKLINE_PADDING;
+    let mut points: Vec<(f64, f64)> = records
+        .iter()
+        .zip(&values)
+        .map(|(record, value)| {
+            let x = if duration > 0.0 {
+                (record.generated_at - first.generated_at).num_seconds() as f64 / duration
+                    * SPARKLINE_WIDTH
+            } else {
+                0.0
+            };
+            let y = if max > min {
+                SPARKLINE_PADDING + (max - value) / (max - min) * usable_height
+            } else {
+                SPARKLINE_HEIGHT / 2.0
+            };
+            (x, y)
+        })
+        .collect();
+
+    if points.len() == 1 {
+        points.push((SPARKLINE_WIDTH, points[0].1));
+    }
+
+    let mut line = String::new();
+    let mut area = format!("M{:.1},{SPARKLINE_HEIGHT:.1}", points[0].0);
+    for (x, y) in &points {
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        write!(line, "{x:.1},{y:.1}")?;
+        write!(area, " L{x:.1},{y:.1}")?;
+    }
+    write!(
+        area,
+        " L{:.1},{SPARKLINE_HEIGHT:.1} Z",
+        points[points.len() - 1].0
+    )?;
+
+    Ok(format!(
+        r#"<path class="spark-area" d="{area}" /><polyline class="spark-line" points="{line}" />"#
+    ))
+}
+
+/// Format a streak's dates as "Mar 3 – Apr 9, 2026", repeating the year only
+/// when the streak crosses into a new one.
+fn format_date_range(start: Option<NaiveDate>, end: Option<NaiveDate>) -> String {
+    match (start, end) {
+        (Some(start), Some(end)) if start == end => end.format("%b %-d, %Y").to_string(),
+        (Some(start), Some(end)) if start.year() == end.year() => format!(
+            "{} \u{2013} {}",
+            start.format("%b %-d"),
+            end.format("%b %-d, %Y")
+        ),
+        (Some(start), Some(end)) => format!(
+            "{} \u{2013} {}",
+            start.format("%b %-d, %Y"),
+            end.format("%b %-d, %Y")
+        ),
+        _ => String::new(),
+    }
+}
+
+fn read_template(card: &CardConfig) -> Result<String> {
+    fs::read_to_string(&card.template)
+        .with_context(|| format!("Failed to read template {}", card.template.display()))
+}
+
+fn write_output(card: &CardConfig, contents: &str) -> Result<()> {
+    // Create output directory if it doesn't exist
+    if let Some(parent) = card.output.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    fs::write(&card.output, contents)
+        .with_context(|| format!("Failed to write {}", card.output.display()))
+}
+
+/// Escape text taken from the API for use in SVG markup.
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
 fn format_number(n: u64) -> String {
     let s = n.to_string();
     let mut result = String::new();
@@ -111,3 +528,25 @@ fn format_number(n: u64) -> String {
 
     result.chars().rev().collect()
 }
+
+fn format_percent(share: f64) -> String {
+    format!("{:.0}%", share * 100.0)
+}
+
+/// Format a change such as "+12 this week", or nothing when there is no
+/// change or not enough history to measure one.
+fn format_delta(change: Option<i64>, days: u32) -> String {
+    let period = if days == 7 {
+        "this week".to_string()
+    } else {
+        format!("in {days} days")
+    };
+
+    match change {
+        Some(change) if change > 0 => format!("+{} {period}", format_number(change.unsigned_abs())),
+        Some(change) if change < 0 => {
+            format!("\u{2212}{} {period}", format_number(change.unsigned_abs()))
+        }
+        _ => String::new(),
+    }
+}
@@ -0,0 +1,192 @@
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use std::{cmp::Reverse, collections::BTreeMap, fs, io::ErrorKind, path::Path};
+
+use crate::{
+    cache::write_atomic,
+    models::{PopularPath, Referrer, TrafficDay},
+    snapshot::format_version,
+    stats::{CloneStats, RepoViews, ViewStats},
+};
+
+/// Version of the traffic history format written by this build.
+pub const TRAFFIC_VERSION: u32 = 1;
+
+/// Traffic of one repository as reported by one run.
+#[derive(Debug, Default)]
+pub struct RepoTraffic {
+    pub views: Vec<TrafficDay>,
+    pub clones: Vec<TrafficDay>,
+    pub referrers: Vec<Referrer>,
+    pub paths: Vec<PopularPath>,
+}
+
+/// Views or clones of one repository on one day.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct DayCounts {
+    pub count: u64,
+    pub uniques: u64,
+}
+
+type Days = BTreeMap<String, BTreeMap<DateTime<Utc>, DayCounts>>;
+
+/// The top referrers and paths of a repository over the 14 days up to the
+/// day they were collected.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Popular {
+    pub referrers: Vec<Referrer>,
+    pub paths: Vec<PopularPath>,
+}
+
+/// Every day of views and clones recorded for each repository.
+///
+/// GitHub only reports the last 14 days, so each `collect` merges what it sees
+/// into this store. Days are keyed by their timestamp, so a day reported by
+/// several runs is kept once, with the highest counts seen: the counts of the
+/// current day keep growing until it ends. Referrers and popular paths only
+/// come as totals over the last 14 days; the last lists collected on each day
+/// are kept.
+#[derive(Serialize, Deserialize)]
+pub struct TrafficHistory {
+    version: u32,
+    /// Stored as `repos`, from when views were all the store kept
+    #[serde(rename = "repos")]
+    views: Days,
+    #[serde(default)]
+    clones: Days,
+    #[serde(default)]
+    popular: BTreeMap<String, BTreeMap<NaiveDate, Popular>>,
+}
+
+impl Default for TrafficHistory {
+    fn default() -> Self {
+        Self {
+            version: TRAFFIC_VERSION,
+            views: BTreeMap::new(),
+            clones: BTreeMap::new(),
+            popular: BTreeMap::new(),
+        }
+    }
+}
+
+impl TrafficHistory {
+    /// Read the store at `path`, or start an empty one if there is none yet.
+    /// A store that cannot be read is an error rather than a fresh start, so
+    /// that the next save does not throw the recorded days away.
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => {
+                return Err(e)
+                    .with_context(|| format!("Failed to read traffic history {}", path.display()))
+            }
+        };
+
+        let version = format_version(&contents)
+            .with_context(|| format!("{} is not a traffic history", path.display()))?;
+        if version > TRAFFIC_VERSION {
+            bail!(
+                "{} has traffic history version {}, but this build only understands up to version {}",
+                path.display(),
+                version,
+                TRAFFIC_VERSION
+            );
+        }
+
+        let mut history: Self = serde_json::from_str(&contents)
+            .with_context(|| format!("Failed to parse traffic history {}", path.display()))?;
+        history.version = TRAFFIC_VERSION;
+        Ok(history)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let contents = serde_json::to_string_pretty(self)?;
+        write_atomic(path, contents.as_bytes())
+    }
+
+    /// Add the traffic reported for each repository on `today`. Returns how
+    /// many days of views and clones were not recorded before.
+    pub fn merge(&mut self, traffic: &BTreeMap<String, RepoTraffic>, today: NaiveDate) -> usize {
+        let mut added = 0;
+        for (repo, reported) in traffic {
+            added += merge_days(&mut self.views, repo, &reported.views);
+            added += merge_days(&mut self.clones, repo, &reported.clones);
+            if !(reported.referrers.is_empty() && reported.paths.is_empty()) {
+                self.popular.entry(repo.clone()).or_default().insert(
+                    today,
+                    Popular {
+                        referrers: reported.referrers.clone(),
+                        paths: reported.paths.clone(),
+                    },
+                );
+            }
+        }
+        added
+    }
+
+    /// Views since the first recorded day, in total and per repository.
+    pub fn view_stats(&self) -> ViewStats {
+        let mut repos: Vec<RepoViews> = self
+            .views
+            .iter()
+            .map(|(repo, days)| RepoViews {
+                repo: repo.clone(),
+                views: days.values().map(|day| day.count).sum(),
+                uniques: days.values().map(|day| day.uniques).sum(),
+            })
+            .filter(|repo| repo.views > 0)
+            .collect();
+        repos.sort_by_key(|repo| Reverse(repo.views));
+
+        ViewStats {
+            total: repos.iter().map(|repo| repo.views).sum(),
+            uniques: repos.iter().map(|repo| repo.uniques).sum(),
+            since: first_day(&self.views),
+            repos,
+        }
+    }
+
+    /// Clones since the first recorded day.
+    pub fn clone_stats(&self) -> CloneStats {
+        CloneStats {
+            total: self
+                .clones
+                .values()
+                .flat_map(BTreeMap::values)
+                .map(|day| day.count)
+                .sum(),
+            since: first_day(&self.clones),
+        }
+    }
+}
+
+/// Add the `reported` days of `repo`, returning how many are new.
+fn merge_days(recorded: &mut Days, repo: &str, reported: &[TrafficDay]) -> usize {
+    if reported.is_empty() {
+        return 0;
+    }
+    let recorded = recorded.entry(repo.to_string()).or_default();
+    let mut added = 0;
+    for day in reported {
+        let counts = recorded.entry(day.timestamp).or_insert_with(|| {
+            added += 1;
+            DayCounts::default()
+        });
+        counts.count = counts.count.max(day.count);
+        counts.uniques = counts.uniques.max(day.uniques);
+    }
+    added
+}
+
+fn first_day(days: &Days) -> Option<NaiveDate> {
+    days.values()
+        .filter_map(|days| days.keys().next())
+        .min()
+        .map(DateTime::date_naive)
+}
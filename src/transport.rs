@@ -0,0 +1,121 @@
+//! The HTTP layer under `GitHubClient`.
+//!
+//! The client builds requests and interprets responses; a `Transport` only
+//! moves them. Besides the real network transport there are the fixture
+//! transports in `fixtures`, and anything else that can answer a request can
+//! stand in for GitHub the same way.
+
+use anyhow::{Context, Result};
+use futures::future::BoxFuture;
+use reqwest::{header::HeaderMap, Client, Method, StatusCode};
+use serde_json::Value;
+use tokio::time::Duration;
+
+use crate::retry;
+
+pub struct HttpRequest {
+    pub method: Method,
+    pub url: String,
+    /// Path relative to the API root, e.g. `/repos/octocat/hello/traffic/views`,
+    /// or `graphql` for GraphQL queries
+    pub path: String,
+    pub headers: HeaderMap,
+    /// JSON body, if any
+    pub body: Option<Value>,
+}
+
+/// A response read in full.
+pub struct HttpResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Vec<u8>,
+}
+
+impl HttpResponse {
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+}
+
+/// Why a request produced no response.
+pub enum SendError {
+    /// A dropped connection or timeout; worth retrying
+    Transient(String),
+    Fatal(anyhow::Error),
+}
+
+impl From<reqwest::Error> for SendError {
+    fn from(error: reqwest::Error) -> Self {
+        if retry::is_retryable_error(&error) {
+            Self::Transient(error.to_string())
+        } else {
+            Self::Fatal(error.into())
+        }
+    }
+}
+
+pub trait Transport: Send + Sync {
+    fn send(&self, request: HttpRequest) -> BoxFuture<'_, Result<HttpResponse, SendError>>;
+
+    /// Whether responses arrive in real time, so that backoff delays are
+    /// worth waiting out. Transports serving canned responses skip them.
+    fn real_time(&self) -> bool {
+        true
+    }
+
+    /// Whether the response cache is read and written. Transports that need
+    /// to see every request, or whose responses do not belong in the cache,
+    /// turn this off.
+    fn use_cache(&self) -> bool {
+        true
+    }
+
+    /// Whether what is collected through this transport may be saved beyond
+    /// the snapshot: contribution ratios, the run history and the traffic
+    /// history. Transports serving canned responses turn this off.
+    fn persist_history(&self) -> bool {
+        true
+    }
+}
+
+/// Sends requests over the network.
+pub struct ReqwestTransport {
+    client: Client,
+}
+
+impl ReqwestTransport {
+    pub fn new() -> Result<Self> {
+        let client = Client::builder()
+            .user_agent("github-stats-generator")
+            .timeout(Duration::from_secs(30))
+            .build()
+            .context("Failed to create HTTP client")?;
+
+        Ok(Self { client })
+    }
+}
+
+impl Transport for ReqwestTransport {
+    fn send(&self, request: HttpRequest) -> BoxFuture<'_, Result<HttpResponse, SendError>> {
+        Box::pin(async move {
+            let mut builder = self
+                .client
+                .request(request.method, &request.url)
+                .headers(request.headers);
+            if let Some(body) = &request.body {
+                builder = builder.json(body);
+            }
+
+            let response = builder.send().await?;
+            let status = response.status();
+            let headers = response.headers().clone();
+            let body = response.bytes().await?.to_vec();
+
+            Ok(HttpResponse {
+                status,
+                headers,
+                body,
+            })
+        })
+    }
+}
@@ -0,0 +1,445 @@
+//! Helpers shared by the integration tests: running the binary, comparing
+//! snapshots, and a mock GitHub for it to talk to.
+
+#![allow(dead_code)]
+
+use std::{
+    env, fs,
+    io::{BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    path::{Path, PathBuf},
+    process::{Command, Output},
+    sync::{Arc, Mutex},
+    thread,
+};
+
+use serde_json::{json, Value};
+
+pub fn manifest_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+}
+
+/// The binary, run in `dir` with all state kept inside it.
+pub fn github_stats(dir: &Path) -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_github-stats"));
+    command
+        .current_dir(dir)
+        .env_clear()
+        .args(["--cache-dir", "cache", "--snapshot", "stats.json"])
+        .args(["--history", "history.jsonl"]);
+    command
+}
+
+pub fn run(mut command: Command) -> Output {
+    let output = command.output().expect("failed to run github-stats");
+    assert!(
+        output.status.success(),
+        "github-stats failed\nstdout:\n{}\nstderr:\n{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+    output
+}
+
+/// The `stats` object of the snapshot written by `collect`.
+pub fn read_stats(dir: &Path) -> Value {
+    let snapshot: Value =
+        serde_json::from_str(&fs::read_to_string(dir.join("stats.json")).unwrap()).unwrap();
+    snapshot["stats"].clone()
+}
+
+pub fn assert_snapshot(name: &str, actual: &str) {
+    let path = manifest_dir().join("tests/snapshots").join(name);
+    if env::var_os("UPDATE_SNAPSHOTS").is_some() {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, actual).unwrap();
+        return;
+    }
+
+    let expected = fs::read_to_string(&path)
+        .unwrap_or_else(|e| panic!("cannot read snapshot {}: {e}", path.display()));
+    assert!(
+        expected == actual,
+        "{name} differs from its snapshot; rerun with UPDATE_SNAPSHOTS=1 if the change is intended\n\
+         --- expected\n{expected}\n--- actual\n{actual}"
+    );
+}
+
+#[derive(Debug, Clone)]
+pub struct MockRequest {
+    pub method: String,
+    /// Path and query string, e.g. `/repos/octo/alpha/traffic/views`
+    pub path: String,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl MockRequest {
+    /// The GraphQL query of a `POST /graphql`, whitespace collapsed.
+    pub fn query(&self) -> Option<String> {
+        let body: Value = serde_json::from_str(&self.body).ok()?;
+        let query = body["query"].as_str()?;
+        Some(query.split_whitespace().collect::<Vec<_>>().join(" "))
+    }
+
+    fn matches(&self, route: &Route) -> bool {
+        match route.kind {
+            RouteKind::Rest => self.method == "GET" && self.path == route.pattern,
+            RouteKind::GraphQl => {
+                self.method == "POST"
+                    && self.path == "/graphql"
+                    && self.query().is_some_and(|q| q.contains(&route.pattern))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MockResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl MockResponse {
+    pub fn json(status: u16, body: &Value) -> Self {
+        Self {
+            status,
+            headers: vec![("Content-Type".to_string(), "application/json".to_string())],
+            body: body.to_string(),
+        }
+    }
+
+    pub fn ok(body: &Value) -> Self {
+        Self::json(200, body)
+    }
+
+    /// The `202 Accepted` GitHub answers while it computes repository statistics.
+    pub fn computing() -> Self {
+        Self::json(202, &json!({}))
+    }
+
+    /// The `304` that answers a conditional request for something unchanged.
+    pub fn not_modified() -> Self {
+        Self {
+            status: 304,
+            headers: Vec::new(),
+            body: String::new(),
+        }
+    }
+
+    /// A GraphQL response carrying `data`.
+    pub fn data(data: &Value) -> Self {
+        Self::ok(&json!({ "data": data }))
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    fn not_found() -> Self {
+        Self::json(404, &json!({ "message": "Not Found" }))
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum RouteKind {
+    Rest,
+    GraphQl,
+}
+
+struct Route {
+    kind: RouteKind,
+    pattern: String,
+    responses: Vec<MockResponse>,
+    served: usize,
+}
+
+#[derive(Default)]
+struct MockState {
+    routes: Vec<Route>,
+    requests: Vec<MockRequest>,
+}
+
+/// An in-process stand-in for the GitHub REST and GraphQL APIs.
+///
+/// Each route answers with its responses in turn and repeats the last one,
+/// so sequences such as `202, 202, 200` or `403, 200` play out as scripted.
+/// Routes added later take precedence, which lets a test override one part
+/// of [`MockGitHub::account`]. Anything unrouted gets a `404`.
+pub struct MockGitHub {
+    url: String,
+    state: Arc<Mutex<MockState>>,
+}
+
+impl MockGitHub {
+    pub fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let state = Arc::new(Mutex::new(MockState::default()));
+
+        let server_state = Arc::clone(&state);
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let state = Arc::clone(&server_state);
+                thread::spawn(move || serve(stream, &state));
+            }
+        });
+
+        Self { url, state }
+    }
+
+    /// Root of the mock REST API, for `--api-url`. GraphQL is served at
+    /// `{url}/graphql`, where the binary looks by default.
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Answer `GET path` with `responses` in turn.
+    pub fn rest(&self, path: &str, responses: Vec<MockResponse>) -> &Self {
+        self.route(RouteKind::Rest, path, responses)
+    }
+
+    /// Answer GraphQL queries containing `pattern` (whitespace collapsed)
+    /// with `responses` in turn.
+    pub fn graphql(&self, pattern: &str, responses: Vec<MockResponse>) -> &Self {
+        self.route(RouteKind::GraphQl, pattern, responses)
+    }
+
+    fn route(&self, kind: RouteKind, pattern: &str, responses: Vec<MockResponse>) -> &Self {
+        assert!(!responses.is_empty(), "route {pattern} has no responses");
+        self.state.lock().unwrap().routes.push(Route {
+            kind,
+            pattern: pattern.to_string(),
+            responses,
+            served: 0,
+        });
+        self
+    }
+
+    /// Every request received so far.
+    pub fn requests(&self) -> Vec<MockRequest> {
+        self.state.lock().unwrap().requests.clone()
+    }
+
+    /// How many requests went to `GET path`.
+    pub fn rest_hits(&self, path: &str) -> usize {
+        self.requests()
+            .iter()
+            .filter(|r| r.method == "GET" && r.path == path)
+            .count()
+    }
+
+    /// How many GraphQL queries contained `pattern`.
+    pub fn graphql_hits(&self, pattern: &str) -> usize {
+        self.requests()
+            .iter()
+            .filter(|r| r.query().is_some_and(|q| q.contains(pattern)))
+            .count()
+    }
+
+    /// Serve a small account, "octo", with one repository, one year of
+    /// contributions and one pull request.
+    pub fn account(&self) -> &Self {
+        self.graphql(
+            "repositories(",
+            vec![MockResponse::data(&json!({
+                "viewer": {
+                    "login": "octo",
+                    "name": "Octo Cat",
+                    "repositories": page(vec![repository("octo/alpha", 5, 1)], None),
+                    "repositoriesContributedTo": page(vec![], None),
+                }
+            }))],
+        );
+        self.graphql(
+            "contributionYears",
+            vec![MockResponse::data(&json!({
+                "viewer": { "contributionsCollection": { "contributionYears": [2024] } }
+            }))],
+        );
+        self.graphql(
+            "year2024:",
+            vec![MockResponse::data(&json!({
+                "viewer": { "year2024": contribution_year(2024, &[3, 4]) }
+            }))],
+        );
+        self.graphql(
+            "pullRequests(",
+            vec![MockResponse::data(&json!({
+                "viewer": {
+                    "pullRequests": page(vec![json!({
+                        "state": "MERGED",
+                        "repository": { "nameWithOwner": "octo/alpha" }
+                    })], None)
+                }
+            }))],
+        );
+        self.rest(
+            "/repos/octo/alpha/stats/contributors",
+            vec![MockResponse::ok(&contributors("octo", 100, 20))],
+        );
+        self.rest(
+            "/repos/octo/alpha/traffic/views",
+            vec![MockResponse::ok(
+                &json!({ "count": 10, "uniques": 4, "views": [] }),
+            )],
+        );
+        self.rest(
+            "/repos/octo/alpha/traffic/clones",
+            vec![MockResponse::ok(
+                &json!({ "count": 0, "uniques": 0, "clones": [] }),
+            )],
+        );
+        self.rest(
+            "/repos/octo/alpha/traffic/popular/referrers",
+            vec![MockResponse::ok(&json!([]))],
+        );
+        self.rest(
+            "/repos/octo/alpha/traffic/popular/paths",
+            vec![MockResponse::ok(&json!([]))],
+        )
+    }
+}
+
+/// A GraphQL connection page. `Value::Null` nodes stand for entries the
+/// token may not see.
+pub fn page(nodes: Vec<Value>, next: Option<&str>) -> Value {
+    json!({
+        "pageInfo": { "hasNextPage": next.is_some(), "endCursor": next },
+        "nodes": nodes,
+    })
+}
+
+pub fn repository(name_with_owner: &str, stars: u64, forks: u64) -> Value {
+    json!({
+        "nameWithOwner": name_with_owner,
+        "stargazers": { "totalCount": stars },
+        "forkCount": forks,
+        "viewerPermission": "ADMIN",
+        "languages": { "edges": [
+            { "size": 1000, "node": { "name": "Rust", "color": "#dea584" } }
+        ] },
+    })
+}
+
+/// A year's contributions collection, with `counts` on consecutive days from
+/// January 1.
+pub fn contribution_year(year: i32, counts: &[u32]) -> Value {
+    let days: Vec<Value> = counts
+        .iter()
+        .enumerate()
+        .map(|(day, count)| {
+            json!({
+                "date": format!("{year}-01-{:02}", day + 1),
+                "contributionCount": count,
+                "color": "#40c463",
+            })
+        })
+        .collect();
+    json!({
+        "totalPullRequestContributions": 1,
+        "totalIssueContributions": 2,
+        "totalPullRequestReviewContributions": 3,
+        "totalRepositoryContributions": 1,
+        "contributionCalendar": {
+            "totalContributions": counts.iter().sum::<u32>(),
+            "weeks": [{ "contributionDays": days }],
+        },
+    })
+}
+
+/// A `/stats/contributors` payload with a single author and week.
+pub fn contributors(login: &str, added: u64, deleted: u64) -> Value {
+    json!([{
+        "author": { "login": login },
+        "total": 1,
+        "weeks": [{ "w": 1_704_067_200, "a": added, "d": deleted, "c": 1 }],
+    }])
+}
+
+/// A `/traffic/views` or `/traffic/clones` payload, with the days listed
+/// under `key`: `count` and one unique visitor or cloner on each day, and
+/// `uniques` over the whole fortnight.
+pub fn traffic_days(key: &str, days: &[(&str, u64)], uniques: u64) -> Value {
+    let entries: Vec<Value> = days
+        .iter()
+        .map(|(day, count)| {
+            json!({ "timestamp": format!("{day}T00:00:00Z"), "count": count, "uniques": 1 })
+        })
+        .collect();
+    let count: u64 = days.iter().map(|(_, count)| count).sum();
+    json!({ "count": count, "uniques": uniques, key: entries })
+}
+
+fn serve(stream: TcpStream, state: &Mutex<MockState>) {
+    let Some(request) = read_request(&stream) else {
+        return;
+    };
+
+    let response = {
+        let mut state = state.lock().unwrap();
+        state.requests.push(request.clone());
+        match state.routes.iter_mut().rev().find(|r| request.matches(r)) {
+            Some(route) => {
+                let index = route.served.min(route.responses.len() - 1);
+                route.served += 1;
+                route.responses[index].clone()
+            }
+            None => MockResponse::not_found(),
+        }
+    };
+
+    let _ = write_response(stream, &response);
+}
+
+fn read_request(stream: &TcpStream) -> Option<MockRequest> {
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    reader.read_line(&mut line).ok()?;
+    let mut parts = line.split_whitespace();
+    let method = parts.next()?.to_string();
+    let path = parts.next()?.to_string();
+
+    let mut headers = Vec::new();
+    loop {
+        line.clear();
+        reader.read_line(&mut line).ok()?;
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        let (name, value) = header.split_once(':')?;
+        headers.push((name.trim().to_lowercase(), value.trim().to_string()));
+    }
+
+    let length = headers
+        .iter()
+        .find(|(name, _)| name == "content-length")
+        .and_then(|(_, value)| value.parse().ok())
+        .unwrap_or(0);
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).ok()?;
+
+    Some(MockRequest {
+        method,
+        path,
+        headers,
+        body: String::from_utf8_lossy(&body).into_owned(),
+    })
+}
+
+fn write_response(mut stream: TcpStream, response: &MockResponse) -> std::io::Result<()> {
+    let mut head = format!("HTTP/1.1 {} Mock\r\n", response.status);
+    for (name, value) in &response.headers {
+        head.push_str(&format!("{name}: {value}\r\n"));
+    }
+    head.push_str(&format!(
+        "Content-Length: {}\r\nConnection: close\r\n\r\n",
+        response.body.len()
+    ));
+    stream.write_all(head.as_bytes())?;
+    stream.write_all(response.body.as_bytes())?;
+    stream.flush()
+}
@@ -0,0 +1,888 @@
+//! End-to-end runs of the binary against a mock GitHub, for the exchanges
+//! that are awkward to capture from the real API: statistics still being
+//! computed, rate limits, failing servers and unusual pagination.
+
+mod common;
+
+use std::{fs, path::Path, process::Command};
+
+use chrono::{Datelike, Utc};
+use serde_json::json;
+use tempfile::TempDir;
+
+use common::{
+    contribution_year, contributors, page, read_stats, repository, run, traffic_days, MockGitHub,
+    MockResponse,
+};
+
+const CONTRIBUTORS: &str = "/repos/octo/alpha/stats/contributors";
+const VIEWS: &str = "/repos/octo/alpha/traffic/views";
+const CLONES: &str = "/repos/octo/alpha/traffic/clones";
+const REFERRERS: &str = "/repos/octo/alpha/traffic/popular/referrers";
+
+/// `collect` against `github`, retrying quickly so that backoff does not slow
+/// the tests down.
+fn collect(dir: &Path, github: &MockGitHub) -> Command {
+    collect_with_config(dir, github, "")
+}
+
+/// `collect` with extra settings for `github-stats.toml`.
+fn collect_with_config(dir: &Path, github: &MockGitHub, config: &str) -> Command {
+    fs::write(
+        dir.join("github-stats.toml"),
+        format!("{config}\n[retry]\nmax_attempts = 3\ninitial_delay_ms = 1\nmax_delay_secs = 1\n"),
+    )
+    .unwrap();
+
+    let mut command = common::github_stats(dir);
+    command.args(["collect", "--user", "octo", "--token", "test"]);
+    command.args(["--api-url", github.url()]);
+    command
+}
+
+#[test]
+fn collects_account() {
+    let github = MockGitHub::start();
+    github.account();
+    let dir = TempDir::new().unwrap();
+    run(collect(dir.path(), &github));
+
+    let stats = read_stats(dir.path());
+    assert_eq!(stats["name"], "Octo Cat");
+    assert_eq!(stats["total_repos"], 1);
+    assert_eq!(stats["total_stars"], 5);
+    assert_eq!(stats["total_contributions"], 7);
+    assert_eq!(stats["lines_added"], 100);
+    assert_eq!(stats["total_views"], 10);
+
+    for request in github.requests() {
+        let scheme = if request.path == "/graphql" {
+            "Bearer"
+        } else {
+            "token"
+        };
+        let authorization = format!("{scheme} test");
+        assert!(
+            request
+                .headers
+                .iter()
+                .any(|(name, value)| name == "authorization" && *value == authorization),
+            "{request:?}"
+        );
+    }
+}
+
+#[test]
+fn empty_variables_fall_through_to_the_config_file() {
+    let github = MockGitHub::start();
+    github.account();
+    let dir = TempDir::new().unwrap();
+    let mut command =
+        collect_with_config(dir.path(), &github, "[collect]\nexclude_forked = true\n");
+    command.env("EXCLUDE_FORKED_REPOS", "");
+    let output = run(command);
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Excluding forked repositories"), "{stdout}");
+}
+
+#[test]
+fn falls_back_to_github_token_when_access_token_is_empty() {
+    let github = MockGitHub::start();
+    github.account();
+    let dir = TempDir::new().unwrap();
+    let mut command = common::github_stats(dir.path());
+    command.args(["collect", "--user", "octo", "--api-url", github.url()]);
+    command
+        .env("ACCESS_TOKEN", "")
+        .env("GITHUB_TOKEN", "fallback");
+    run(command);
+
+    assert!(github.requests().iter().all(|request| request
+        .headers
+        .iter()
+        .any(|(name, value)| name == "authorization" && value.ends_with(" fallback"))));
+}
+
+#[test]
+fn treats_unknown_exclude_forked_values_as_true() {
+    let github = MockGitHub::start();
+    github.account();
+    let dir = TempDir::new().unwrap();
+    let mut command = collect(dir.path(), &github);
+    command.env("EXCLUDE_FORKED_REPOS", "forks");
+    let output = run(command);
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Excluding forked repositories"), "{stdout}");
+}
+
+#[test]
+fn rejects_host_names_that_are_not_file_names() {
+    let github = MockGitHub::start();
+    let dir = TempDir::new().unwrap();
+    for name in ["../x", "a/b", ".."] {
+        let hosts = format!(
+            "[[hosts]]\nname = {name:?}\napi_url = \"{}\"\ntoken_env = \"WORK_TOKEN\"\n",
+            github.url()
+        );
+        let output = collect_with_config(dir.path(), &github, &hosts)
+            .output()
+            .unwrap();
+
+        assert_eq!(output.status.code(), Some(2), "{name}");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("hosts[0].name"), "{stderr}");
+    }
+    assert!(!dir.path().join("x").exists());
+}
+
+#[test]
+fn ignores_the_actions_api_url() {
+    let github = MockGitHub::start();
+    github.account();
+    let dir = TempDir::new().unwrap();
+    fs::write(
+        dir.path().join("github-stats.toml"),
+        format!("[github]\napi_url = \"{}\"\n", github.url()),
+    )
+    .unwrap();
+    let mut command = common::github_stats(dir.path());
+    command.args(["collect", "--user", "octo", "--token", "test"]);
+    command.env("GITHUB_API_URL", "http://127.0.0.1:9");
+    run(command);
+
+    assert_eq!(read_stats(dir.path())["name"], "Octo Cat");
+}
+
+#[test]
+fn waits_while_statistics_are_computed() {
+    let github = MockGitHub::start();
+    github.account().rest(
+        CONTRIBUTORS,
+        vec![
+            MockResponse::computing(),
+            MockResponse::computing(),
+            MockResponse::ok(&contributors("octo", 300, 40)),
+        ],
+    );
+    let dir = TempDir::new().unwrap();
+    run(collect(dir.path(), &github));
+
+    assert_eq!(github.rest_hits(CONTRIBUTORS), 3);
+    let stats = read_stats(dir.path());
+    assert_eq!(stats["lines_added"], 300);
+    assert_eq!(stats["lines_deleted"], 40);
+}
+
+#[test]
+fn backs_off_after_rate_limits() {
+    let github = MockGitHub::start();
+    github
+        .account()
+        .rest(
+            VIEWS,
+            vec![
+                MockResponse::json(
+                    403,
+                    &json!({ "message": "You have exceeded a secondary rate limit." }),
+                )
+                .header("Retry-After", "0"),
+                MockResponse::ok(&json!({ "count": 25, "uniques": 1, "views": [] })),
+            ],
+        )
+        .graphql(
+            "contributionYears",
+            vec![
+                MockResponse::ok(&json!({
+                    "errors": [{ "type": "RATE_LIMITED", "message": "API rate limit exceeded" }]
+                }))
+                .header("X-RateLimit-Remaining", "0")
+                .header("X-RateLimit-Reset", "1"),
+                MockResponse::data(&json!({
+                    "viewer": { "contributionsCollection": { "contributionYears": [2024] } }
+                })),
+            ],
+        );
+    let dir = TempDir::new().unwrap();
+    let output = run(collect(dir.path(), &github));
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("REST rate limit hit"), "{stdout}");
+    assert!(stdout.contains("GraphQL rate limit hit"), "{stdout}");
+    assert_eq!(github.rest_hits(VIEWS), 2);
+    assert_eq!(github.graphql_hits("contributionYears"), 2);
+    let stats = read_stats(dir.path());
+    assert_eq!(stats["total_views"], 25);
+    assert_eq!(stats["total_contributions"], 7);
+}
+
+#[test]
+fn gives_up_when_the_quota_resets_too_late() {
+    let github = MockGitHub::start();
+    github.account().graphql(
+        "repositories(",
+        vec![MockResponse::data(&json!({
+            "viewer": {
+                "login": "octo",
+                "name": "Octo Cat",
+                "repositories": page(vec![repository("octo/alpha", 5, 1)], None),
+                "repositoriesContributedTo": page(vec![], None),
+            }
+        }))
+        .header("X-RateLimit-Remaining", "0")
+        .header("X-RateLimit-Reset", "4102444800")],
+    );
+    let dir = TempDir::new().unwrap();
+    let output = collect(dir.path(), &github).output().unwrap();
+
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("does not reset soon enough"), "{stderr}");
+    assert_eq!(github.graphql_hits("contributionYears"), 0);
+}
+
+#[test]
+fn retries_server_errors() {
+    let github = MockGitHub::start();
+    github.account().graphql(
+        "pullRequests(",
+        vec![
+            MockResponse::json(502, &json!({ "message": "Bad Gateway" })),
+            MockResponse::data(&json!({
+                "viewer": { "pullRequests": page(vec![], None) }
+            })),
+        ],
+    );
+    let dir = TempDir::new().unwrap();
+    run(collect(dir.path(), &github));
+
+    assert_eq!(github.graphql_hits("pullRequests("), 2);
+}
+
+#[test]
+fn gives_up_when_retries_run_out() {
+    let github = MockGitHub::start();
+    github.account().graphql(
+        "repositories(",
+        vec![MockResponse::json(
+            502,
+            &json!({ "message": "Bad Gateway" }),
+        )],
+    );
+    let dir = TempDir::new().unwrap();
+    let output = collect(dir.path(), &github).output().unwrap();
+
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("failed after 3 attempts"), "{stderr}");
+    assert_eq!(github.graphql_hits("repositories("), 3);
+    assert!(!dir.path().join("stats.json").exists());
+}
+
+#[test]
+fn follows_every_page() {
+    let github = MockGitHub::start();
+    let viewer = |repositories, contributed| {
+        MockResponse::data(&json!({
+            "viewer": {
+                "login": "octo",
+                "name": null,
+                "repositories": repositories,
+                "repositoriesContributedTo": contributed,
+            }
+        }))
+    };
+    github
+        .account()
+        // Owned repositories span three pages, the last of them empty; a
+        // repository hidden from the token comes back as a null node.
+        .graphql(
+            r#"isFork: false, after: null"#,
+            vec![viewer(
+                page(
+                    vec![repository("octo/alpha", 5, 1), json!(null)],
+                    Some("owned-1"),
+                ),
+                page(vec![repository("other/gamma", 100, 10)], Some("contrib-1")),
+            )],
+        )
+        .graphql(
+            r#"isFork: false, after: "owned-1""#,
+            vec![viewer(
+                page(vec![repository("octo/beta", 2, 0)], Some("owned-2")),
+                page(vec![repository("other/delta", 7, 3)], None),
+            )],
+        )
+        .graphql(
+            r#"isFork: false, after: "owned-2""#,
+            vec![viewer(
+                page(vec![], None),
+                // The contributed list has no more pages, so it is asked for
+                // from the start again; repeats are not counted twice.
+                page(vec![repository("other/gamma", 100, 10)], None),
+            )],
+        );
+    for repo in ["octo/beta", "other/gamma", "other/delta"] {
+        github.rest(
+            &format!("/repos/{repo}/stats/contributors"),
+            vec![MockResponse::ok(&json!([]))],
+        );
+        github.rest(
+            &format!("/repos/{repo}/traffic/views"),
+            vec![MockResponse::ok(
+                &json!({ "count": 1, "uniques": 1, "views": [] }),
+            )],
+        );
+    }
+    let dir = TempDir::new().unwrap();
+    run(collect(dir.path(), &github));
+
+    assert_eq!(github.graphql_hits("isFork: false"), 3);
+    let stats = read_stats(dir.path());
+    assert_eq!(stats["name"], "octo");
+    assert_eq!(stats["total_repos"], 4);
+    assert_eq!(stats["total_stars"], 114);
+    assert_eq!(stats["total_forks"], 14);
+}
+
+#[test]
+fn revalidates_expired_cache_entries() {
+    let github = MockGitHub::start();
+    github
+        .account()
+        .rest(
+            VIEWS,
+            vec![
+                MockResponse::ok(&json!({ "count": 10, "uniques": 1, "views": [] }))
+                    .header("ETag", r#""views-1""#),
+                MockResponse::not_modified().header("ETag", r#""views-1""#),
+            ],
+        )
+        .rest(
+            CONTRIBUTORS,
+            vec![
+                MockResponse::ok(&contributors("octo", 100, 20))
+                    .header("Last-Modified", "Mon, 01 Jan 2024 00:00:00 GMT"),
+                MockResponse::not_modified(),
+            ],
+        );
+    let dir = TempDir::new().unwrap();
+    let expired = "[cache]\nexpiry_hours = 0\n";
+    run(collect_with_config(dir.path(), &github, expired));
+    run(collect_with_config(dir.path(), &github, expired));
+
+    let header = |path: &str, name: &str| {
+        github
+            .requests()
+            .iter()
+            .filter(|r| r.path == path)
+            .map(|r| {
+                r.headers
+                    .iter()
+                    .find(|(n, _)| n == name)
+                    .map(|(_, v)| v.clone())
+            })
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        header(VIEWS, "if-none-match"),
+        [None, Some(r#""views-1""#.to_string())]
+    );
+    assert_eq!(
+        header(CONTRIBUTORS, "if-modified-since"),
+        [None, Some("Mon, 01 Jan 2024 00:00:00 GMT".to_string())]
+    );
+
+    let stats = read_stats(dir.path());
+    assert_eq!(stats["total_views"], 10);
+    assert_eq!(stats["lines_added"], 100);
+
+    // Expired entries with validators are only pruned by age
+    let cache = |args: &[&str]| {
+        let mut command = common::github_stats(dir.path());
+        command.arg("cache").args(args);
+        String::from_utf8(run(command).stdout).unwrap()
+    };
+    let views = format!("rest:{}{VIEWS}", github.url());
+    cache(&["prune"]);
+    assert!(cache(&["ls"]).contains(&views));
+    cache(&["prune", "--older-than", "0"]);
+    assert!(!cache(&["ls"]).contains(&views));
+}
+
+#[test]
+fn caches_graphql_responses_when_asked() {
+    let year = Utc::now().year();
+    let current = format!("year{year}:");
+    let github = MockGitHub::start();
+    github
+        .account()
+        .graphql(
+            "contributionYears",
+            vec![MockResponse::data(&json!({
+                "viewer": { "contributionsCollection": { "contributionYears": [year, 2024] } }
+            }))],
+        )
+        .graphql(
+            &current,
+            vec![MockResponse::data(&json!({
+                "viewer": { format!("year{year}"): contribution_year(year, &[1, 1]) }
+            }))],
+        );
+    let dir = TempDir::new().unwrap();
+    let expired = "[cache]\ngraphql_expiry_hours = 0\n";
+    for _ in 0..2 {
+        let mut command = collect_with_config(dir.path(), &github, expired);
+        command.arg("--cache-graphql");
+        run(command);
+    }
+
+    // Past years are kept; everything else has expired
+    assert_eq!(github.graphql_hits("year2024:"), 1);
+    assert_eq!(github.graphql_hits(&current), 2);
+    assert_eq!(github.graphql_hits("repositories("), 2);
+    assert_eq!(read_stats(dir.path())["total_contributions"], 9);
+
+    // They still expire, at the end of the year, so that prune can remove
+    // them once the list of past years has changed
+    let mut command = common::github_stats(dir.path());
+    command.args(["cache", "ls"]);
+    let listing = String::from_utf8(run(command).stdout).unwrap();
+    let past = listing
+        .lines()
+        .find(|line| line.contains("year2024:"))
+        .unwrap();
+    assert!(past.contains(" fresh "), "{listing}");
+
+    // Without the flag nothing comes from the GraphQL cache
+    run(collect_with_config(dir.path(), &github, expired));
+    assert_eq!(github.graphql_hits("year2024:"), 2);
+}
+
+#[test]
+fn manages_the_cache() {
+    let github = MockGitHub::start();
+    github.account();
+    let dir = TempDir::new().unwrap();
+    let cache = |args: &[&str]| {
+        let mut command = common::github_stats(dir.path());
+        command.arg("cache").args(args);
+        String::from_utf8(run(command).stdout).unwrap()
+    };
+
+    let output = run(collect(dir.path(), &github));
+    assert!(String::from_utf8_lossy(&output.stdout).contains(": 0 hits, 5 misses"));
+    let output = run(collect(dir.path(), &github));
+    assert!(String::from_utf8_lossy(&output.stdout).contains(": 5 hits, 0 misses"));
+
+    let views = format!("rest:{}{VIEWS}", github.url());
+    let contributors = format!("rest:{}{CONTRIBUTORS}", github.url());
+    let listing = cache(&["ls"]);
+    assert!(listing.contains(&views), "{listing}");
+    assert!(listing.contains(&contributors), "{listing}");
+    assert!(listing.contains("(ratio_cache.json)"), "{listing}");
+
+    let output = cache(&["clear", "--pattern", "*/traffic/*"]);
+    assert!(output.contains("Removed 4 responses"), "{output}");
+    let listing = cache(&["ls"]);
+    assert!(!listing.contains(&views), "{listing}");
+    assert!(listing.contains(&contributors), "{listing}");
+
+    // Entries stored with no time to live or to stand in for failed
+    // refreshes, and no validators to revalidate them with, are of no
+    // further use
+    run(collect_with_config(
+        dir.path(),
+        &github,
+        "[cache]\nexpiry_hours = 0\nstale_hours = 0\n",
+    ));
+    let output = cache(&["prune"]);
+    assert!(output.contains("Removed 5 files"), "{output}");
+    let listing = cache(&["ls"]);
+    assert!(!listing.contains("rest:"), "{listing}");
+    assert!(listing.contains("(ratio_cache.json)"), "{listing}");
+}
+
+#[test]
+fn replaces_unusable_cache_files() {
+    let github = MockGitHub::start();
+    github.account();
+    let dir = TempDir::new().unwrap();
+    run(collect(dir.path(), &github));
+
+    // Cut every file short, as a run killed mid-write used to, except one
+    // entry that claims a format from a future build
+    let cache_dir = dir.path().join("cache");
+    let mut files: Vec<_> = fs::read_dir(&cache_dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    files.sort();
+    for path in &files {
+        let contents = fs::read_to_string(path).unwrap();
+        fs::write(path, &contents[..contents.len() / 2]).unwrap();
+    }
+    let future = files
+        .iter()
+        .find(|path| !path.ends_with("ratio_cache.json"))
+        .unwrap();
+    fs::write(future, r#"{ "version": 99, "layout": "unknown" }"#).unwrap();
+    fs::write(cache_dir.join(".interrupted.json.1-0.tmp"), "{").unwrap();
+
+    let output = run(collect(dir.path(), &github));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("corrupt entry"), "{stdout}");
+    assert!(stdout.contains("entry has format version 99"), "{stdout}");
+    assert!(
+        stdout.contains("[ratios] ignoring") && stdout.contains("corrupt file"),
+        "{stdout}"
+    );
+    assert!(stdout.contains("5 unusable entries replaced"), "{stdout}");
+    assert_eq!(read_stats(dir.path())["lines_added"], 100);
+
+    let ratios: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(cache_dir.join("ratio_cache.json")).unwrap())
+            .unwrap();
+    assert_eq!(ratios["version"], 1);
+    assert_eq!(ratios["ratios"]["octo/alpha"], 1.0);
+
+    let mut command = common::github_stats(dir.path());
+    command.args(["cache", "prune"]);
+    let output = String::from_utf8(run(command).stdout).unwrap();
+    assert!(output.contains("Removed 1 files"), "{output}");
+}
+
+#[test]
+fn keeps_the_cache_in_other_backends() {
+    let github = MockGitHub::start();
+    github.account();
+    let dir = TempDir::new().unwrap();
+    let sqlite = "[cache]\nbackend = \"sqlite\"\n";
+
+    let output = run(collect_with_config(dir.path(), &github, sqlite));
+    assert!(String::from_utf8_lossy(&output.stdout).contains(": 0 hits, 5 misses"));
+    let output = run(collect_with_config(dir.path(), &github, sqlite));
+    assert!(String::from_utf8_lossy(&output.stdout).contains(": 5 hits, 0 misses"));
+
+    let mut files: Vec<_> = fs::read_dir(dir.path().join("cache"))
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect();
+    files.sort();
+    assert_eq!(files, ["cache.sqlite", "ratio_cache.json"]);
+
+    let mut command = common::github_stats(dir.path());
+    command.args(["cache", "clear", "--pattern", "*/traffic/*"]);
+    let output = String::from_utf8(run(command).stdout).unwrap();
+    assert!(output.contains("Removed 4 responses"), "{output}");
+    let mut command = common::github_stats(dir.path());
+    command.args(["cache", "ls"]);
+    let listing = String::from_utf8(run(command).stdout).unwrap();
+    assert!(listing.contains(&format!("rest:{}{CONTRIBUTORS}", github.url())));
+    assert!(!listing.contains(VIEWS), "{listing}");
+
+    // The flag overrides the file, and nothing outlives a run in memory
+    for _ in 0..2 {
+        let mut command = collect_with_config(dir.path(), &github, sqlite);
+        command.args(["--cache-backend", "memory"]);
+        let output = run(command);
+        assert!(String::from_utf8_lossy(&output.stdout).contains(": 0 hits, 5 misses"));
+    }
+}
+
+#[test]
+fn applies_cache_policies() {
+    let github = MockGitHub::start();
+    github.account();
+    let dir = TempDir::new().unwrap();
+    let config = r#"
+[cache]
+expiry_hours = 0
+graphql_expiry_hours = 0
+
+[[cache.policies]]
+endpoint = "*/stats/contributors"
+expiry_hours = 24
+
+[[cache.policies]]
+endpoint = "ContributionYears"
+expiry_hours = 24
+"#;
+    for _ in 0..2 {
+        let mut command = collect_with_config(dir.path(), &github, config);
+        command.arg("--cache-graphql");
+        run(command);
+    }
+
+    assert_eq!(github.rest_hits(CONTRIBUTORS), 1);
+    assert_eq!(github.rest_hits(VIEWS), 2);
+    assert_eq!(github.graphql_hits("contributionYears"), 1);
+    assert_eq!(github.graphql_hits("repositories("), 2);
+}
+
+#[test]
+fn serves_stale_responses_when_a_refresh_fails() {
+    let github = MockGitHub::start();
+    github.account();
+    let dir = TempDir::new().unwrap();
+    let expired = "[cache]\nexpiry_hours = 0\n";
+    run(collect_with_config(dir.path(), &github, expired));
+
+    github.rest(
+        VIEWS,
+        vec![MockResponse::json(
+            500,
+            &json!({ "message": "Server Error" }),
+        )],
+    );
+    let output = run(collect_with_config(dir.path(), &github, expired));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("serving the response stored"), "{stdout}");
+    assert!(
+        stdout.contains("1 stale entries served after failed refreshes"),
+        "{stdout}"
+    );
+    assert_eq!(read_stats(dir.path())["total_views"], 10);
+
+    // Past its stale time the entry is dropped rather than served
+    run(collect_with_config(
+        dir.path(),
+        &github,
+        "[cache]\nexpiry_hours = 0\nstale_hours = 0\n",
+    ));
+    assert_eq!(read_stats(dir.path())["total_views"], 0);
+}
+
+#[test]
+fn keeps_traffic_beyond_two_weeks() {
+    let github = MockGitHub::start();
+    github.account();
+    let dir = TempDir::new().unwrap();
+    let views = |days: &[(&str, u64)]| vec![MockResponse::ok(&traffic_days("views", days, 2))];
+
+    github.rest(VIEWS, views(&[("2024-03-01", 4), ("2024-03-02", 1)]));
+    run(collect(dir.path(), &github));
+
+    // The next run no longer sees the first day, and sees more of the second
+    github.rest(VIEWS, views(&[("2024-03-02", 3), ("2024-03-03", 5)]));
+    let mut command = collect(dir.path(), &github);
+    command.args(["--cache-dir", "other-cache"]);
+    run(command);
+
+    let stats = read_stats(dir.path());
+    assert_eq!(stats["total_views"], 8);
+    assert_eq!(stats["unique_views"], 2);
+    assert_eq!(
+        stats["repo_views"],
+        json!([{ "repo": "octo/alpha", "views": 8, "uniques": 2 }])
+    );
+    assert_eq!(stats["view_history"]["total"], 12);
+    assert_eq!(stats["view_history"]["uniques"], 3);
+    assert_eq!(stats["view_history"]["since"], "2024-03-01");
+    assert_eq!(
+        stats["view_history"]["repos"],
+        json!([{ "repo": "octo/alpha", "views": 12, "uniques": 3 }])
+    );
+
+    let traffic: serde_json::Value = serde_json::from_str(
+        &fs::read_to_string(dir.path().join("generated/traffic.json")).unwrap(),
+    )
+    .unwrap();
+    assert_eq!(traffic["repos"]["octo/alpha"].as_object().unwrap().len(), 3);
+}
+
+#[test]
+fn collects_clones_and_referrers() {
+    let github = MockGitHub::start();
+    github.account();
+    let dir = TempDir::new().unwrap();
+    let clones = |days: &[(&str, u64)]| vec![MockResponse::ok(&traffic_days("clones", days, 2))];
+    github.rest(
+        REFERRERS,
+        vec![MockResponse::ok(&json!([
+            { "referrer": "Google", "count": 3, "uniques": 2 },
+            { "referrer": "github.com", "count": 7, "uniques": 4 },
+        ]))],
+    );
+
+    github.rest(CLONES, clones(&[("2024-03-01", 2), ("2024-03-02", 1)]));
+    run(collect(dir.path(), &github));
+
+    github.rest(CLONES, clones(&[("2024-03-02", 4)]));
+    let mut command = collect(dir.path(), &github);
+    command.args(["--cache-dir", "other-cache"]);
+    run(command);
+
+    let stats = read_stats(dir.path());
+    assert_eq!(stats["total_clones"], 4);
+    assert_eq!(stats["unique_cloners"], 2);
+    assert_eq!(stats["clone_history"]["total"], 6);
+    assert_eq!(stats["clone_history"]["since"], "2024-03-01");
+    assert_eq!(stats["top_referrers"][0]["referrer"], "github.com");
+    assert_eq!(stats["top_referrers"][1]["referrer"], "Google");
+
+    let traffic: serde_json::Value = serde_json::from_str(
+        &fs::read_to_string(dir.path().join("generated/traffic.json")).unwrap(),
+    )
+    .unwrap();
+    assert_eq!(
+        traffic["clones"]["octo/alpha"].as_object().unwrap().len(),
+        2
+    );
+    let popular = traffic["popular"]["octo/alpha"].as_object().unwrap();
+    assert_eq!(popular.len(), 1);
+}
+
+#[test]
+fn reads_traffic_only_with_push_access() {
+    let github = MockGitHub::start();
+    let contributed = |name, permission| {
+        let mut repo = repository(name, 1, 0);
+        repo["viewerPermission"] = json!(permission);
+        repo
+    };
+    github.account().graphql(
+        "repositories(",
+        vec![MockResponse::data(&json!({
+            "viewer": {
+                "login": "octo",
+                "name": "Octo Cat",
+                "repositories": page(vec![repository("octo/alpha", 5, 1)], None),
+                "repositoriesContributedTo": page(vec![
+                    contributed("other/gamma", "READ"),
+                    contributed("other/delta", "WRITE"),
+                ], None),
+            }
+        }))],
+    );
+    let dir = TempDir::new().unwrap();
+    run(collect(dir.path(), &github));
+
+    assert_eq!(github.rest_hits(VIEWS), 1);
+    assert_eq!(github.rest_hits("/repos/other/delta/traffic/views"), 1);
+    assert_eq!(github.rest_hits("/repos/other/gamma/traffic/views"), 0);
+    assert_eq!(github.rest_hits("/repos/other/gamma/traffic/clones"), 0);
+    assert_eq!(read_stats(dir.path())["total_repos"], 3);
+}
+
+#[test]
+fn escapes_referrers_in_the_traffic_card() {
+    let github = MockGitHub::start();
+    github.account().rest(
+        REFERRERS,
+        vec![MockResponse::ok(&json!([
+            { "referrer": "R&D <wiki>", "count": 3, "uniques": 2 },
+        ]))],
+    );
+    let dir = TempDir::new().unwrap();
+    run(collect(dir.path(), &github));
+
+    let templates = dir.path().join("templates");
+    fs::create_dir(&templates).unwrap();
+    for entry in fs::read_dir(Path::new(env!("CARGO_MANIFEST_DIR")).join("templates")).unwrap() {
+        let entry = entry.unwrap();
+        fs::copy(entry.path(), templates.join(entry.file_name())).unwrap();
+    }
+    let mut render = common::github_stats(dir.path());
+    render.arg("render");
+    run(render);
+
+    let svg = fs::read_to_string(dir.path().join("generated/traffic.svg")).unwrap();
+    assert!(svg.contains(">R&amp;D &lt;wiki&gt;</text>"), "{svg}");
+}
+
+/// The streaks computed from a 2024 calendar with `counts` on consecutive
+/// days from January 1, the last of them standing for today.
+fn streaks(counts: &[u32]) -> serde_json::Value {
+    let github = MockGitHub::start();
+    github.account().graphql(
+        "year2024:",
+        vec![MockResponse::data(&json!({
+            "viewer": { "year2024": contribution_year(2024, counts) }
+        }))],
+    );
+    let dir = TempDir::new().unwrap();
+    run(collect(dir.path(), &github));
+    read_stats(dir.path())["streaks"].clone()
+}
+
+#[test]
+fn keeps_the_current_streak_while_today_is_pending() {
+    let streaks = streaks(&[1, 1, 1, 0]);
+    assert_eq!(streaks["current"], 3);
+    assert_eq!(streaks["current_start"], "2024-01-01");
+    assert_eq!(streaks["current_end"], "2024-01-03");
+}
+
+#[test]
+fn ends_the_current_streak_after_a_missed_day() {
+    let streaks = streaks(&[1, 1, 0, 0]);
+    assert_eq!(streaks["current"], 0);
+    assert_eq!(streaks["current_start"], json!(null));
+    assert_eq!(streaks["longest"], 2);
+}
+
+#[test]
+fn keeps_the_first_of_tied_longest_streaks() {
+    let streaks = streaks(&[2, 2, 0, 1, 1]);
+    assert_eq!(streaks["longest"], 2);
+    assert_eq!(streaks["longest_start"], "2024-01-01");
+    assert_eq!(streaks["longest_end"], "2024-01-02");
+    assert_eq!(streaks["current"], 2);
+    assert_eq!(streaks["current_start"], "2024-01-04");
+}
+
+#[test]
+fn computes_no_streaks_without_contributions() {
+    let github = MockGitHub::start();
+    github.account().graphql(
+        "contributionYears",
+        vec![MockResponse::data(&json!({
+            "viewer": { "contributionsCollection": { "contributionYears": [] } }
+        }))],
+    );
+    let dir = TempDir::new().unwrap();
+    run(collect(dir.path(), &github));
+
+    let stats = read_stats(dir.path());
+    assert_eq!(stats["contribution_calendar"], json!([]));
+    let streaks = &stats["streaks"];
+    assert_eq!(streaks["current"], 0);
+    assert_eq!(streaks["longest"], 0);
+    assert_eq!(streaks["active_days"], 0);
+    assert_eq!(streaks["busiest_day"], json!(null));
+}
+
+#[test]
+fn compares_against_the_oldest_run_when_none_is_old_enough() {
+    let github = MockGitHub::start();
+    github.account();
+    let dir = TempDir::new().unwrap();
+    let history = || {
+        let mut command = common::github_stats(dir.path());
+        command.args(["history", "--days", "30"]);
+        String::from_utf8(run(command).stdout).unwrap()
+    };
+
+    run(collect(dir.path(), &github));
+    let output = history();
+    assert!(output.contains("Stars          n/a"), "{output}");
+
+    github.graphql(
+        "repositories(",
+        vec![MockResponse::data(&json!({
+            "viewer": {
+                "login": "octo",
+                "name": "Octo Cat",
+                "repositories": page(vec![repository("octo/alpha", 8, 1)], None),
+                "repositoriesContributedTo": page(vec![], None),
+            }
+        }))],
+    );
+    run(collect(dir.path(), &github));
+    let output = history();
+    assert!(output.contains("2 runs recorded"), "{output}");
+    assert!(output.contains("Stars          +3"), "{output}");
+    assert!(output.contains("Forks          +0"), "{output}");
+}
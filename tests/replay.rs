@@ -0,0 +1,158 @@
+//! End-to-end runs of the binary against recorded API fixtures.
+//!
+//! `tests/fixtures/basic` holds the responses for a small synthetic account:
+//! paginated repositories, a repository hidden by SAML single sign-on, a
+//! `202` while contributor statistics are computed, traffic the token may not
+//! read, and two years of contributions. The collected statistics and every
+//! rendered card are compared with the files in `tests/snapshots`. After an
+//! intended change in output, regenerate them with
+//! `UPDATE_SNAPSHOTS=1 cargo test`.
+
+mod common;
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use tempfile::TempDir;
+
+use common::{assert_snapshot, manifest_dir, read_stats, run, MockGitHub};
+
+/// The binary replaying the basic fixtures. The API URL points at a closed
+/// port, so any request that misses the fixtures fails.
+fn github_stats(dir: &Path) -> Command {
+    let mut command = common::github_stats(dir);
+    command
+        .arg("--replay")
+        .arg(manifest_dir().join("tests/fixtures/basic"));
+    command
+}
+
+fn collect(dir: &Path) -> Command {
+    let mut command = github_stats(dir);
+    command.args(["collect", "--user", "octo", "--token", "test"]);
+    command.args(["--api-url", "http://127.0.0.1:9"]);
+    command
+}
+
+#[test]
+fn collect_matches_snapshot() {
+    let dir = TempDir::new().unwrap();
+    let output = run(collect(dir.path()));
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("[graphql] skipping viewer.repositoriesContributedTo.nodes[1]"));
+
+    let stats = serde_json::to_string_pretty(&read_stats(dir.path())).unwrap() + "\n";
+    assert_snapshot("stats.json", &stats);
+}
+
+/// Everything a collection may record in `dir` outside the snapshot, by path.
+fn recorded_files(dir: &Path) -> BTreeMap<PathBuf, String> {
+    let cache = fs::read_dir(dir.join("cache"))
+        .unwrap()
+        .map(|entry| entry.unwrap().path());
+    let histories = [
+        dir.join("history.jsonl"),
+        dir.join("generated/traffic.json"),
+    ];
+    cache
+        .chain(histories)
+        .map(|path| {
+            let contents = fs::read_to_string(&path).unwrap();
+            (path, contents)
+        })
+        .collect()
+}
+
+#[test]
+fn replay_leaves_the_cache_and_histories_alone() {
+    // Record a run of another account under the URLs the replay requests
+    let github = MockGitHub::start();
+    github.account();
+    let dir = TempDir::new().unwrap();
+    let mut command = common::github_stats(dir.path());
+    command.args([
+        "collect",
+        "--user",
+        "octo",
+        "--token",
+        "test",
+        "--cache-graphql",
+    ]);
+    command.args(["--api-url", github.url()]);
+    run(command);
+    let recorded = recorded_files(dir.path());
+    assert!(recorded
+        .keys()
+        .any(|path| path.ends_with("ratio_cache.json")));
+
+    let mut command = github_stats(dir.path());
+    command.args([
+        "collect",
+        "--user",
+        "octo",
+        "--token",
+        "test",
+        "--cache-graphql",
+    ]);
+    command.args(["--api-url", github.url()]);
+    run(command);
+
+    let stats = serde_json::to_string_pretty(&read_stats(dir.path())).unwrap() + "\n";
+    assert_snapshot("stats.json", &stats);
+    assert_eq!(recorded_files(dir.path()), recorded);
+}
+
+#[test]
+fn strict_mode_rejects_partial_data() {
+    let dir = TempDir::new().unwrap();
+    let output = collect(dir.path()).arg("--strict").output().unwrap();
+
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("strict mode"), "{stderr}");
+    assert!(!dir.path().join("stats.json").exists());
+}
+
+#[test]
+fn render_matches_snapshots() {
+    let dir = TempDir::new().unwrap();
+    let templates = dir.path().join("templates");
+    fs::create_dir(&templates).unwrap();
+    for entry in fs::read_dir(manifest_dir().join("templates")).unwrap() {
+        let entry = entry.unwrap();
+        fs::copy(entry.path(), templates.join(entry.file_name())).unwrap();
+    }
+
+    run(collect(dir.path()));
+    // Replayed runs are not recorded, so give the trends card a history of
+    // this one run
+    let snapshot: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(dir.path().join("stats.json")).unwrap()).unwrap();
+    fs::write(
+        dir.path().join("history.jsonl"),
+        serde_json::to_string(&snapshot).unwrap() + "\n",
+    )
+    .unwrap();
+
+    let mut render = github_stats(dir.path());
+    render.arg("render");
+    run(render);
+
+    for card in [
+        "overview",
+        "languages",
+        "trends",
+        "calendar",
+        "streaks",
+        "traffic",
+    ] {
+        let name = format!("{card}.svg");
+        let svg = fs::read_to_string(dir.path().join("generated").join(&name)).unwrap();
+        assert_snapshot(&name, &svg);
+    }
+}